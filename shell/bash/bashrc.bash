@@ -0,0 +1,3 @@
+source "$BASHDIR/pre.bash"
+[[ $- == *i* ]] && source "$BASHDIR/interactive.bash"
+source "$BASHDIR/post.bash"
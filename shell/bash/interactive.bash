@@ -0,0 +1,8 @@
+# Starship
+command -v starship > /dev/null && eval "$(starship init bash)"
+
+# Direnv
+command -v direnv > /dev/null && eval "$(direnv hook bash)"
+
+# Zoxide
+command -v zoxide > /dev/null && eval "$(zoxide init bash)"
@@ -0,0 +1,4 @@
+source "$SHDIR/post.sh"
+
+# Local configuration that is specific to each machine.
+LOCAL_POST_BASH="$BASHDIR/.local.post.bash" && test -f $LOCAL_POST_BASH && source $LOCAL_POST_BASH
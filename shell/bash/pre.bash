@@ -0,0 +1,14 @@
+source "$SHDIR/pre.sh"
+
+# History
+export HISTFILE="$LOCALDIR/.bash_history"
+export HISTSIZE=999999999
+export HISTFILESIZE=999999999
+export HISTCONTROL=ignoreboth:erasedups
+shopt -s histappend
+
+# Rye
+RYE_ENV="$RYE_HOME/env" && test -f $RYE_ENV && source $RYE_ENV
+
+# Local configuration that is specific to each machine.
+LOCAL_PRE_BASH="$BASHDIR/.local.pre.bash" && test -f $LOCAL_PRE_BASH && source $LOCAL_PRE_BASH
@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result, WithPath};
+use crate::install::InstallConfig;
+use crate::prefix::Prefix;
+use crate::ssh::SshKey;
+use crate::utils::load_toml;
+
+/// `config/bootstrap.toml`, the setup of a new machine run by `dot init --bootstrap`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BootstrapConfig {
+    /// Predefined binaries of `dot install --config`.
+    binaries: Vec<String>,
+    /// Predefined keys of `config/ssh/key.toml`, generated after `skm` is installed.
+    ssh: Vec<String>,
+    /// Commands run inside the dot repository with `Prefix::bin()` on `$PATH`.
+    step: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Step {
+    name: String,
+    command: Vec<String>,
+    /// Ids of the tasks to run before this step: `binary.<name>`, `ssh.<key>` or `step.<name>`.
+    #[serde(default)]
+    needs: Vec<String>,
+}
+
+enum Task<'a> {
+    Binary(InstallConfig),
+    Ssh(&'a str),
+    Step(&'a Step),
+}
+
+/// Ids of the tasks already done, kept across runs to resume after a failure and cleared once
+/// every task succeeded.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct BootstrapState {
+    done: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BootstrapReport {
+    done: Vec<String>,
+    skipped: Vec<String>,
+}
+
+pub fn state_path(prefix: &Prefix) -> std::path::PathBuf {
+    prefix.state().join("bootstrap.toml")
+}
+
+impl BootstrapConfig {
+    fn tasks(&self) -> Result<Vec<(String, Task<'_>, Vec<String>)>> {
+        let skm = format!("binary.{}", InstallConfig::Skm.name());
+        let mut tasks = vec![];
+        for name in &self.binaries {
+            let config = InstallConfig::from_str(name, true)
+                .map_err(|e| Error::Config(format!("invalid binary {}: {}", name, e)))?;
+            tasks.push((format!("binary.{}", config.name()), Task::Binary(config), vec![]));
+        }
+        let has_skm = tasks.iter().any(|(id, ..)| *id == skm);
+        for key in &self.ssh {
+            let needs = if has_skm { vec![skm.clone()] } else { vec![] };
+            tasks.push((format!("ssh.{}", key), Task::Ssh(key), needs));
+        }
+        for step in &self.step {
+            tasks.push((format!("step.{}", step.name), Task::Step(step), step.needs.clone()));
+        }
+        Ok(tasks)
+    }
+
+    /// The tasks sorted so that every task comes after the ones it needs,
+    /// otherwise in the order of `bootstrap.toml`.
+    fn plan(&self) -> Result<Vec<(String, Task<'_>)>> {
+        fn visit(
+            id: &str,
+            needs: &HashMap<String, Vec<String>>,
+            visiting: &mut HashSet<String>,
+            order: &mut Vec<String>,
+        ) -> Result<()> {
+            if order.iter().any(|done| done == id) {
+                return Ok(());
+            }
+            if !visiting.insert(id.to_owned()) {
+                return Err(Error::Config(format!("bootstrap task {} depends on itself", id)));
+            }
+            for need in &needs[id] {
+                if !needs.contains_key(need) {
+                    return Err(Error::Config(format!(
+                        "bootstrap task {} needs unknown task {}",
+                        id, need
+                    )));
+                }
+                visit(need, needs, visiting, order)?;
+            }
+            visiting.remove(id);
+            order.push(id.to_owned());
+            Ok(())
+        }
+
+        let tasks = self.tasks()?;
+        let needs: HashMap<_, _> =
+            tasks.iter().map(|(id, _, needs)| (id.clone(), needs.clone())).collect();
+        let mut order = vec![];
+        for (id, ..) in &tasks {
+            visit(id, &needs, &mut HashSet::new(), &mut order)?;
+        }
+
+        let mut tasks: HashMap<_, _> = tasks.into_iter().map(|(id, task, _)| (id, task)).collect();
+        Ok(order
+            .into_iter()
+            .map(|id| {
+                let task = tasks.remove(&id).unwrap();
+                (id, task)
+            })
+            .collect())
+    }
+}
+
+impl Task<'_> {
+    /// Whether the result of the task is already there, such as after the state was cleared.
+    fn is_done(&self, prefix: &Prefix) -> Result<bool> {
+        Ok(match self {
+            Task::Binary(config) => {
+                let versions = InstallConfig::load_predefined_version(prefix)?;
+                match (versions.get(config), config.installed_version(prefix)) {
+                    (Some(version), Some(stdout)) => {
+                        InstallConfig::reports_version(&stdout, version)
+                    }
+                    _ => false,
+                }
+            }
+            Task::Ssh(key) => prefix.skm().join(key).join("id_ed25519").exists(),
+            Task::Step(_) => false,
+        })
+    }
+
+    fn run(&self, prefix: &Prefix) -> Result<()> {
+        match self {
+            Task::Binary(config) => config.download(prefix, None).map(|_| ()),
+            Task::Ssh(key) => SshKey::get_predefined_key(prefix, key)?.generate(prefix).map(|_| ()),
+            Task::Step(step) => {
+                let Some((program, args)) = step.command.split_first() else {
+                    return Err(Error::Config(format!(
+                        "bootstrap step {} has no command",
+                        step.name
+                    )));
+                };
+                let path = std::env::var_os("PATH").unwrap_or_default();
+                let path = std::env::join_paths(
+                    std::iter::once(prefix.bin()).chain(std::env::split_paths(&path)),
+                )
+                .map_err(|e| Error::Config(format!("invalid PATH: {}", e)))?;
+                let mut command = std::process::Command::new(program);
+                command.args(args).current_dir(prefix.dot()).env("PATH", path);
+                if prefix.dry_run(format_args!("run {:?}", command)) {
+                    return Ok(());
+                }
+                prefix.run(&mut command)
+            }
+        }
+    }
+}
+
+fn save_state(prefix: &Prefix, state: &BootstrapState) -> Result<()> {
+    if prefix.is_dry_run() {
+        return Ok(());
+    }
+    let path = state_path(prefix);
+    let content = toml::to_string(state)
+        .map_err(|e| Error::Config(format!("can not serialize bootstrap state: {}", e)))?;
+    std::fs::write(&path, content).with_path(&path)
+}
+
+/// Run the tasks of `config/bootstrap.toml` which are not done yet.
+pub fn bootstrap(prefix: &Prefix) -> Result<BootstrapReport> {
+    let config: BootstrapConfig = load_toml(prefix.config().join("bootstrap.toml"))?;
+    let path = state_path(prefix);
+    let mut state: BootstrapState =
+        if path.exists() { load_toml(&path)? } else { Default::default() };
+
+    let mut report = BootstrapReport::default();
+    for (id, task) in config.plan()? {
+        if state.done.contains(&id) || task.is_done(prefix)? {
+            log::info!(task = id; "Bootstrap task is already done");
+            report.skipped.push(id);
+            continue;
+        }
+        log::info!(task = id; "Running bootstrap task");
+        if let Err(e) = task.run(prefix) {
+            log::error!(task = id; "Bootstrap task failed, run `dot init --bootstrap` to resume");
+            return Err(e);
+        }
+        state.done.push(id.clone());
+        save_state(prefix, &state)?;
+        report.done.push(id);
+    }
+    // The next run starts over, such as after a step changed, but keeps the binaries and keys
+    // which are already there.
+    if path.exists() && !prefix.dry_run(format_args!("remove {:?}", path)) {
+        std::fs::remove_file(&path).with_path(&path)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn ids(config: &str) -> Result<Vec<String>> {
+        let config: BootstrapConfig = toml::from_str(config).unwrap();
+        Ok(config.plan()?.into_iter().map(|(id, _)| id).collect())
+    }
+
+    #[test]
+    fn test_plan() {
+        assert_eq!(
+            ids(r#"
+ssh = ["github"]
+binaries = ["rye", "skm"]
+
+[[step]]
+name = "sync"
+command = ["rye", "sync"]
+needs = ["step.toolchain"]
+
+[[step]]
+name = "toolchain"
+command = ["rye", "toolchain", "fetch"]
+needs = ["binary.rye"]
+"#)
+            .unwrap(),
+            ["binary.rye", "binary.skm", "ssh.github", "step.toolchain", "step.sync"]
+        );
+        assert!(matches!(
+            ids("[[step]]\nname = \"a\"\ncommand = [\"true\"]\nneeds = [\"step.a\"]\n"),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            ids("[[step]]\nname = \"a\"\ncommand = [\"true\"]\nneeds = [\"binary.rye\"]\n"),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_bootstrap_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let config = r#"
+[[step]]
+name = "first"
+command = ["true"]
+
+[[step]]
+name = "second"
+command = ["%s"]
+needs = ["step.first"]
+"#;
+        let path = prefix.config().join("bootstrap.toml");
+        std::fs::write(&path, config.replace("%s", "false")).unwrap();
+        assert!(matches!(bootstrap(&prefix), Err(Error::CommandStatus { .. })));
+
+        std::fs::write(&path, config.replace("%s", "true")).unwrap();
+        let report = bootstrap(&prefix).unwrap();
+        assert_eq!(report.skipped, ["step.first"]);
+        assert_eq!(report.done, ["step.second"]);
+        assert!(!state_path(&prefix).exists());
+
+        let report = bootstrap(&prefix).unwrap();
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.done, ["step.first", "step.second"]);
+    }
+
+    #[test]
+    fn test_bootstrap_rerun_keep_ssh_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        std::fs::write(
+            prefix.config().join("bootstrap.toml"),
+            "ssh = [\"github\"]\n\n[[step]]\nname = \"first\"\ncommand = [\"true\"]\n",
+        )
+        .unwrap();
+        // The key generated by a completed run, the next run must not regenerate it.
+        let key_dir = prefix.skm().join("github");
+        std::fs::create_dir_all(&key_dir).unwrap();
+        std::fs::write(key_dir.join("id_ed25519"), "private").unwrap();
+
+        let report = bootstrap(&prefix).unwrap();
+        assert_eq!(report.skipped, ["ssh.github"]);
+        assert_eq!(report.done, ["step.first"]);
+        assert_eq!(std::fs::read_to_string(key_dir.join("id_ed25519")).unwrap(), "private");
+
+        let report = bootstrap(&prefix).unwrap();
+        assert_eq!(report.skipped, ["ssh.github"]);
+        assert_eq!(report.done, ["step.first"]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+use crate::Cli;
+use crate::error::{Result, WithPath};
+use crate::prefix::Prefix;
+use crate::utils::load_predefined_and_local;
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for.
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+fn predefined_names(path: impl AsRef<Path>) -> Vec<String> {
+    let path = path.as_ref();
+    match load_predefined_and_local::<toml::Value>(path) {
+        Ok(configs) => {
+            let mut names: Vec<_> = configs.into_keys().collect();
+            names.sort();
+            names
+        }
+        Err(e) => {
+            log::debug!(path:? = path, error:% = e; "Skipping predefined names");
+            vec![]
+        }
+    }
+}
+
+fn with_names(arg: clap::Arg, names: &[String]) -> clap::Arg {
+    if names.is_empty() {
+        arg
+    } else {
+        arg.value_parser(PossibleValuesParser::new(
+            names.iter().map(|name| PossibleValue::new(name.clone())),
+        ))
+    }
+}
+
+/// The `dot` command whose `--config` arguments complete the predefined names of this prefix.
+pub fn command(prefix: &Prefix) -> clap::Command {
+    let profiles = predefined_names(prefix.config_git().join("profile.toml"));
+    let keys = predefined_names(prefix.config_ssh().join("key.toml"));
+    let links = predefined_names(prefix.config_link().join("link.toml"));
+    Cli::command()
+        .mut_subcommand("link", |link| link.mut_arg("names", |arg| with_names(arg, &links)))
+        .mut_subcommand("ssh", |ssh| ssh.mut_arg("config", |arg| with_names(arg, &keys)))
+        .mut_subcommand("git", |git| {
+            git.mut_subcommand("profile", |profile| {
+                profile.mut_arg("config", |arg| with_names(arg, &profiles))
+            })
+            .mut_subcommand("clone", |clone| clone.mut_arg("config", |arg| with_names(arg, &keys)))
+        })
+}
+
+fn generate(prefix: &Prefix, shell: Shell, buf: &mut impl std::io::Write) {
+    let mut command = command(prefix);
+    let name = command.get_name().to_owned();
+    clap_complete::generate(shell, &mut command, name, buf);
+}
+
+/// Write the zsh completion function into `Prefix::completion()` which is on `$fpath`.
+pub fn install_zsh_completion(prefix: &Prefix) -> Result<()> {
+    let path = prefix.completion().join("_dot");
+    if prefix.dry_run(format_args!("write zsh completion to {:?}", path)) {
+        return Ok(());
+    }
+    log::info!(path:? = path; "Installing zsh completion");
+    let mut buf = vec![];
+    generate(prefix, Shell::Zsh, &mut buf);
+    std::fs::write(&path, buf).with_path(&path)
+}
+
+pub fn entry_completions(prefix: &Prefix, args: CompletionsArgs) -> Result<()> {
+    generate(prefix, args.shell, &mut std::io::stdout());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_complete_predefined_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        std::fs::write(
+            prefix.config_git().join("profile.toml"),
+            "[work]\nname = \"a\"\nemail = \"b\"\n",
+        )
+        .unwrap();
+        std::fs::write(prefix.config_ssh().join("key.toml"), "[personal]\nhostname = \"c\"\n")
+            .unwrap();
+
+        install_zsh_completion(&prefix).unwrap();
+        let script = std::fs::read_to_string(prefix.completion().join("_dot")).unwrap();
+        assert!(script.starts_with("#compdef dot"));
+        assert!(script.contains("work"));
+        assert!(script.contains("personal"));
+
+        // `dot git clone --config` takes a predefined ssh key.
+        let command = command(&prefix);
+        let clone = command.find_subcommand("git").unwrap().find_subcommand("clone").unwrap();
+        let config = clone.get_arguments().find(|arg| arg.get_id() == "config").unwrap();
+        let names: Vec<_> =
+            config.get_possible_values().iter().map(|value| value.get_name().to_owned()).collect();
+        assert_eq!(names, ["personal"]);
+    }
+}
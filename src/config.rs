@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result, WithPath};
+use crate::prefix::{Layout, Prefix, XdgDir};
+
+/// Defaults of the dot environment, overridden by the command line arguments.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct DotConfig {
+    /// Url of the dot git repository cloned by `dot init`.
+    pub repo: String,
+    /// Owner prepended to `dot git clone` repositories without one.
+    pub owner: String,
+    /// Branch pulled from the remote repositories.
+    pub branch: String,
+    /// Environment variable holding the default git profile.
+    pub git_profile_env: String,
+    /// Environment variable holding the default ssh key.
+    pub ssh_key_env: String,
+    /// Directory layout of the dot environment, `dot init` migrates an existing one.
+    pub layout: Layout,
+}
+
+impl Default for DotConfig {
+    fn default() -> Self {
+        Self {
+            repo: "https://github.com/vnghia/dot.git".into(),
+            owner: "vnghia".into(),
+            branch: "main".into(),
+            git_profile_env: "DOT_GIT_DEFAULT_PROFILE".into(),
+            ssh_key_env: "DOT_SSH_DEFAULT_KEY".into(),
+            layout: Layout::Legacy,
+        }
+    }
+}
+
+impl DotConfig {
+    /// Machine-level `dot.toml` outside the dot repository, read before `dot init` clones it.
+    pub fn machine_path(prefix: &Prefix) -> PathBuf {
+        prefix.xdg(XdgDir::Config).join("dot.toml")
+    }
+
+    pub fn load(prefix: &Prefix) -> Result<Self> {
+        let path = prefix.config().join("dot.toml");
+        let mut table = toml::Table::new();
+        // Keys of the machine-level file and then `.local.toml` replace the ones of `dot.toml`.
+        for path in [path.clone(), Self::machine_path(prefix), path.with_file_name(".local.toml")] {
+            if path.exists() {
+                log::debug!(path:? = path; "Loading dot config");
+                let content = std::fs::read_to_string(&path).with_path(&path)?;
+                table.extend(toml::from_str::<toml::Table>(&content).with_path(&path)?);
+            }
+        }
+        table.try_into().map_err(|e| Error::Config(format!("invalid dot config: {}", e)))
+    }
+
+    pub fn default_profile(&self) -> Option<String> {
+        std::env::var(&self.git_profile_env).ok().or_else(|| self.default_key())
+    }
+
+    pub fn default_key(&self) -> Option<String> {
+        std::env::var(&self.ssh_key_env).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_load_non_existent() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        assert_eq!(DotConfig::load(&prefix).unwrap(), DotConfig::default());
+    }
+
+    #[test]
+    fn test_load_predefined_and_local() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        std::fs::create_dir_all(prefix.config()).unwrap();
+        std::fs::write(
+            prefix.config().join("dot.toml"),
+            "repo = \"https://github.com/fork/dot.git\"\nowner = \"fork\"\n",
+        )
+        .unwrap();
+        std::fs::write(prefix.config().join(".local.toml"), "owner = \"me\"\nbranch = \"dev\"\n")
+            .unwrap();
+        assert_eq!(DotConfig::load(&prefix).unwrap(), DotConfig {
+            repo: "https://github.com/fork/dot.git".into(),
+            owner: "me".into(),
+            branch: "dev".into(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_load_machine_before_clone() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let path = DotConfig::machine_path(&prefix);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "repo = \"https://github.com/fork/dot.git\"\n").unwrap();
+        // `dot init` has not cloned the dot repository yet.
+        assert!(!prefix.dot().exists());
+        assert_eq!(DotConfig::load(&prefix).unwrap().repo, "https://github.com/fork/dot.git");
+
+        std::fs::create_dir_all(prefix.config()).unwrap();
+        std::fs::write(prefix.config().join("dot.toml"), "repo = \"https://github.com/a/b.git\"\n")
+            .unwrap();
+        assert_eq!(DotConfig::load(&prefix).unwrap().repo, "https://github.com/fork/dot.git");
+    }
+
+    #[test]
+    fn test_load_unknown_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        std::fs::create_dir_all(prefix.config()).unwrap();
+        std::fs::write(prefix.config().join("dot.toml"), "branches = \"dev\"\n").unwrap();
+        assert!(matches!(DotConfig::load(&prefix), Err(Error::Config(_))));
+    }
+}
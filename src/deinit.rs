@@ -0,0 +1,263 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgAction, Args, ValueEnum};
+use serde::Serialize;
+
+use crate::error::{Result, WithPath};
+use crate::init::{Shell, block};
+use crate::install::InstallConfig;
+use crate::link::{created_links, unlink};
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::ssh::SshKey;
+
+#[derive(Debug, Args)]
+pub struct DeinitArgs {
+    /// Custom rc files passed to `dot init --rc-file`.
+    #[arg(long)]
+    pub rc_file: Vec<String>,
+    /// Also remove `.dot/.local` with the installed binaries, hooks and completions,
+    /// or their XDG directories.
+    #[arg(long)]
+    pub local: bool,
+    /// Also remove the `.dot` checkout, implies `--local`.
+    #[arg(long)]
+    pub dot: bool,
+    /// Never delete the ssh keys managed by skm, use `--keep-keys false` to delete them too.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub keep_keys: bool,
+    /// Delete the listed files, otherwise only print them.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    ManagedBlock,
+    IncludeLine,
+    Link,
+    File,
+    Directory,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::ManagedBlock => "managed block of",
+            Kind::IncludeLine => "include line of",
+            Kind::Link => "link",
+            Kind::File => "file",
+            Kind::Directory => "directory",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+struct Removal {
+    kind: Kind,
+    path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct DeinitReport {
+    deleted: bool,
+    removals: Vec<Removal>,
+    kept: Vec<PathBuf>,
+}
+
+impl Report for DeinitReport {
+    fn print(&self) {
+        let verb = if self.deleted { "Removed" } else { "Would remove" };
+        for removal in &self.removals {
+            println!("{} {} {}", verb, removal.kind, removal.path.display());
+        }
+        for path in &self.kept {
+            println!("Keeping {}", path.display());
+        }
+        if !self.deleted && !self.removals.is_empty() {
+            println!("Nothing was deleted, rerun with --yes to delete the files above.");
+        }
+    }
+}
+
+/// Everything `args` removes, and the key material it keeps.
+fn plan(prefix: &Prefix, args: &DeinitArgs) -> Result<(Vec<Removal>, Vec<PathBuf>)> {
+    let mut removals = vec![];
+    let mut rc_paths: Vec<_> =
+        Shell::value_variants().iter().flat_map(|shell| shell.rc_paths(prefix)).collect();
+    rc_paths.extend(args.rc_file.iter().map(|rc_file| prefix.prefix().join(rc_file)));
+    rc_paths.sort();
+    rc_paths.dedup();
+    for path in rc_paths {
+        if path.exists() && block::has_block(&std::fs::read_to_string(&path).with_path(&path)?) {
+            removals.push(Removal { kind: Kind::ManagedBlock, path });
+        }
+    }
+
+    let generated = SshKey::generated_ssh_configs(prefix)?;
+    // The include line and the directory are kept for the configs written by hand.
+    let is_emptied = !prefix.ssh_config().exists()
+        || std::fs::read_dir(prefix.ssh_config()).with_path(prefix.ssh_config())?.count()
+            == generated.len();
+    removals.extend(generated.into_iter().map(|path| Removal { kind: Kind::File, path }));
+    if is_emptied {
+        if SshKey::is_ssh_config_dir_included(prefix)? {
+            removals.push(Removal { kind: Kind::IncludeLine, path: prefix.ssh().join("config") });
+        }
+        if prefix.ssh_config().exists() {
+            removals.push(Removal { kind: Kind::Directory, path: prefix.ssh_config() });
+        }
+    }
+    removals
+        .extend(created_links(prefix)?.into_iter().map(|path| Removal { kind: Kind::Link, path }));
+
+    let mut kept = vec![];
+    let mut dirs = vec![];
+    if args.dot || args.local {
+        dirs.extend([prefix.local(), prefix.state(), prefix.cache()]);
+    }
+    if args.dot {
+        dirs.push(prefix.dot());
+    }
+    // The legacy layout nests all of them inside the dot repository.
+    dirs.dedup();
+    let all = dirs.clone();
+    dirs.retain(|dir| {
+        dir.exists() && !all.iter().any(|other| other != dir && dir.starts_with(other))
+    });
+    if !dirs.is_empty() {
+        if args.keep_keys && prefix.skm().exists() {
+            kept.push(prefix.skm());
+        }
+        // `~/.local/bin` of the XDG layout is shared so only the known binaries are removed.
+        if !dirs.iter().any(|dir| prefix.bin().starts_with(dir)) {
+            removals.extend(
+                InstallConfig::value_variants()
+                    .iter()
+                    .map(|config| prefix.bin().join(config.name()))
+                    .filter(|path| path.exists())
+                    .map(|path| Removal { kind: Kind::File, path }),
+            );
+        }
+        removals.extend(dirs.into_iter().map(|path| Removal { kind: Kind::Directory, path }));
+    }
+    Ok((removals, kept))
+}
+
+/// Remove `path` recursively, except `kept` and the directories leading to it.
+fn remove_dir_except(path: &Path, kept: &[PathBuf]) -> Result<()> {
+    if !kept.iter().any(|kept| kept.starts_with(path)) {
+        return std::fs::remove_dir_all(path).with_path(path);
+    }
+    if kept.iter().any(|kept| kept == path) {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(path).with_path(path)? {
+        let entry = entry.with_path(path)?;
+        let entry_path = entry.path();
+        if entry.file_type().with_path(&entry_path)?.is_dir() {
+            remove_dir_except(&entry_path, kept)?;
+        } else {
+            std::fs::remove_file(&entry_path).with_path(&entry_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn apply(prefix: &Prefix, removals: &[Removal], kept: &[PathBuf]) -> Result<()> {
+    if removals.iter().any(|removal| removal.kind == Kind::Link) {
+        unlink(prefix, &[], false)?;
+    }
+    for Removal { kind, path } in removals {
+        match kind {
+            Kind::ManagedBlock => block::remove(prefix, path)?,
+            Kind::IncludeLine => SshKey::exclude_ssh_config_dir(prefix)?,
+            Kind::Link => {}
+            Kind::File => {
+                if !prefix.dry_run(format_args!("remove {:?}", path)) {
+                    std::fs::remove_file(path).with_path(path)?;
+                }
+            }
+            Kind::Directory => {
+                if !prefix.dry_run(format_args!("remove directory {:?}", path)) {
+                    log::info!(path:? = path, kept:? = kept; "Removing directory");
+                    remove_dir_except(path, kept)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn entry_deinit(prefix: &Prefix, args: DeinitArgs) -> Result<()> {
+    let (removals, kept) = plan(prefix, &args)?;
+    if args.yes {
+        apply(prefix, &removals, &kept)?;
+    }
+    prefix.emit(&DeinitReport { deleted: args.yes && !prefix.is_dry_run(), removals, kept })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_deinit_keep_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let zshenv = prefix.prefix().join(".zshenv");
+        std::fs::write(&zshenv, "export CONDA=1\n").unwrap();
+        Shell::Zsh.generate_rc(&prefix, None).unwrap();
+        SshKey::include_ssh_config_dir(&prefix).unwrap();
+        let key = prefix.skm().join("github").join("id_ed25519");
+        std::fs::create_dir_all(key.parent().unwrap()).unwrap();
+        std::fs::write(&key, "").unwrap();
+
+        let args =
+            DeinitArgs { rc_file: vec![], local: false, dot: true, keep_keys: true, yes: true };
+        let (removals, kept) = plan(&prefix, &args).unwrap();
+        assert_eq!(removals, [
+            Removal { kind: Kind::ManagedBlock, path: zshenv.clone() },
+            Removal { kind: Kind::IncludeLine, path: prefix.ssh().join("config") },
+            Removal { kind: Kind::Directory, path: prefix.ssh_config() },
+            Removal { kind: Kind::Directory, path: prefix.dot() },
+        ]);
+        assert_eq!(kept, [prefix.skm()]);
+
+        apply(&prefix, &removals, &kept).unwrap();
+        assert_eq!(std::fs::read_to_string(&zshenv).unwrap(), "export CONDA=1\n");
+        assert!(!SshKey::is_ssh_config_dir_included(&prefix).unwrap());
+        assert!(!prefix.ssh_config().exists());
+        assert!(key.exists());
+        assert!(!prefix.bin().exists());
+        assert!(!prefix.config().exists());
+    }
+
+    #[test]
+    fn test_deinit_keep_hand_written_ssh_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        SshKey::include_ssh_config_dir(&prefix).unwrap();
+        let generated = prefix.ssh_config().join("github");
+        std::fs::write(&generated, "# AUTO GENERATED FILE. DO NOT EDIT\n\nHost github\n").unwrap();
+        let written = prefix.ssh_config().join("work");
+        std::fs::write(&written, "Host work\n").unwrap();
+
+        let args =
+            DeinitArgs { rc_file: vec![], local: false, dot: false, keep_keys: true, yes: true };
+        let (removals, kept) = plan(&prefix, &args).unwrap();
+        assert_eq!(removals, [Removal { kind: Kind::File, path: generated.clone() }]);
+
+        apply(&prefix, &removals, &kept).unwrap();
+        assert!(!generated.exists());
+        assert!(written.exists());
+        assert!(SshKey::is_ssh_config_dir_included(&prefix).unwrap());
+    }
+}
@@ -0,0 +1,415 @@
+use std::fmt::Display;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+
+use clap::{Args, ValueEnum};
+use git2::{Repository, StatusOptions};
+use serde::Serialize;
+
+use crate::error::{Error, Result, WithContext, WithPath};
+use crate::git::{checkout_ref, fetch, pull};
+use crate::init::{Shell, remembered_ref};
+use crate::install::InstallConfig;
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::ssh::SshKey;
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Apply the suggested fixes where possible.
+    #[arg(long)]
+    pub fix: bool,
+    /// The corresponding shell of the dotfile environments.
+    #[arg(short, long, value_enum, default_value_t = Shell::Zsh)]
+    pub shell: Shell,
+    /// Custom .bashrc/.zshenv/dot.fish in case of read-only default rc file.
+    #[arg(long)]
+    pub rc_file: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        })
+    }
+}
+
+type Fix = Box<dyn FnOnce(&Prefix) -> Result<()>>;
+
+#[derive(Serialize)]
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+    suggestion: Option<String>,
+    #[serde(skip)]
+    fix: Option<Fix>,
+}
+
+impl Check {
+    fn new(
+        status: Status,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        suggestion: Option<String>,
+    ) -> Self {
+        Self { name: name.into(), status, detail: detail.into(), suggestion, fix: None }
+    }
+
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(Status::Pass, name, detail, None)
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, suggestion: &str) -> Self {
+        Self::new(Status::Warn, name, detail, Some(suggestion.to_owned()))
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, suggestion: &str) -> Self {
+        Self::new(Status::Fail, name, detail, Some(suggestion.to_owned()))
+    }
+
+    fn with_fix(mut self, fix: impl FnOnce(&Prefix) -> Result<()> + 'static) -> Self {
+        self.fix = Some(Box::new(fix));
+        self
+    }
+}
+
+fn check_rc(prefix: &Prefix, shell: Shell, rc_file: Option<&str>) -> Result<Check> {
+    let path = shell.rc_path(prefix, rc_file);
+    let rc_file = rc_file.map(str::to_owned);
+    let fix = move |prefix: &Prefix| shell.generate_rc(prefix, rc_file.as_deref());
+
+    let check = if !path.exists() {
+        Check::fail("rc file", format!("{:?} does not exist", path), "run `dot init`")
+    } else {
+        let current = std::fs::read_to_string(&path).with_path(&path)?;
+        if shell.is_rc_up_to_date(prefix, &current) {
+            return Ok(Check::pass("rc file", format!("{:?}", path)));
+        } else if current.contains(&*prefix.dot().to_string_lossy())
+            || current.contains(&prefix.home_relative(&prefix.dot(), "$HOME"))
+        {
+            Check::warn(
+                "rc file",
+                format!("{:?} differs from the generated content", path),
+                "run `dot init`",
+            )
+        } else {
+            Check::fail(
+                "rc file",
+                format!("{:?} does not point at {:?}", path, prefix.dot()),
+                "run `dot init`",
+            )
+        }
+    };
+    Ok(check.with_fix(fix))
+}
+
+fn check_path(prefix: &Prefix) -> Check {
+    let bin_dir = prefix.bin();
+    if std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|path| path == bin_dir))
+    {
+        Check::pass("$PATH", format!("{:?}", bin_dir))
+    } else {
+        Check::warn("$PATH", format!("{:?} is not on $PATH", bin_dir), "restart the shell")
+    }
+}
+
+fn check_ssh_include(prefix: &Prefix) -> Result<Check> {
+    let ssh_config_path = prefix.ssh().join("config");
+    Ok(if SshKey::is_ssh_config_dir_included(prefix)? {
+        Check::pass("ssh include", format!("{:?}", ssh_config_path))
+    } else {
+        Check::fail(
+            "ssh include",
+            format!("{:?} does not include {}", ssh_config_path, Prefix::SSH_CONFIG_DIR_NAME),
+            "run `dot ssh`",
+        )
+        .with_fix(SshKey::include_ssh_config_dir)
+    })
+}
+
+fn check_keys(prefix: &Prefix) -> Result<Vec<Check>> {
+    let skm_dir = prefix.skm();
+    if !skm_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut checks = vec![];
+    for entry in std::fs::read_dir(&skm_dir).with_path(&skm_dir)? {
+        let entry = entry.with_path(&skm_dir)?;
+        let private_path = entry.path().join("id_ed25519");
+        if !entry.path().is_dir() || !private_path.exists() {
+            continue;
+        }
+
+        let name = format!("ssh key {}", entry.file_name().to_string_lossy());
+        let mode = private_path.metadata().with_path(&private_path)?.permissions().mode() & 0o777;
+        checks.push(if mode == 0o600 {
+            Check::pass(name, format!("{:?}", private_path))
+        } else {
+            Check::fail(
+                name,
+                format!("{:?} has permissions {:o}", private_path, mode),
+                "chmod 600 the private key",
+            )
+            .with_fix(move |prefix| {
+                if prefix.dry_run(format_args!("chmod 600 {:?}", private_path)) {
+                    return Ok(());
+                }
+                std::fs::set_permissions(&private_path, Permissions::from_mode(0o600))
+                    .with_path(&private_path)
+            })
+        });
+    }
+    Ok(checks)
+}
+
+fn check_binaries(prefix: &Prefix) -> Result<Vec<Check>> {
+    if !prefix.config_binary().join("version.toml").exists() {
+        return Ok(vec![Check::warn("binaries", "version.toml does not exist", "run `dot init`")]);
+    }
+
+    let versions = InstallConfig::load_version(prefix)?;
+    let mut checks = vec![];
+    for config in InstallConfig::value_variants().iter().copied() {
+        let bin_path = prefix.bin().join(config.name());
+        if !bin_path.exists() {
+            continue;
+        }
+
+        let name = format!("binary {}", config.name());
+        let Some(version) = versions.get(&config) else {
+            continue;
+        };
+        let suggestion = format!(
+            "run `dot install -c {}`",
+            config.to_possible_value().map(|value| value.get_name().to_owned()).unwrap_or_default()
+        );
+        let fix = move |prefix: &Prefix| config.download(prefix, None).map(|_| ());
+
+        checks.push(match config.installed_version(prefix) {
+            Some(stdout) if InstallConfig::reports_version(&stdout, version) => {
+                Check::pass(name, version.as_str())
+            }
+            Some(stdout) => Check::warn(
+                name,
+                format!(
+                    "expected {} but got {}",
+                    version,
+                    stdout.lines().next().unwrap_or_default()
+                ),
+                &suggestion,
+            )
+            .with_fix(fix),
+            None => {
+                Check::fail(name, format!("{:?} does not run", bin_path), &suggestion).with_fix(fix)
+            }
+        });
+    }
+    Ok(checks)
+}
+
+fn check_repo(prefix: &Prefix) -> Result<Vec<Check>> {
+    let dot_dir = prefix.dot();
+    let Ok(repo) = Repository::open(&dot_dir) else {
+        return Ok(vec![Check::warn(
+            "dot repo",
+            format!("{:?} is not a git repository", dot_dir),
+            "run `dot init`",
+        )]);
+    };
+
+    let changes = repo
+        .statuses(Some(StatusOptions::new().include_untracked(true).include_ignored(false)))
+        .with_context("can not read the dot repository status")?
+        .len();
+    let mut checks = vec![if changes == 0 {
+        Check::pass("dot repo clean", format!("{:?}", dot_dir))
+    } else {
+        Check::warn(
+            "dot repo clean",
+            format!("{} uncommitted changes", changes),
+            "commit or stash the changes",
+        )
+    }];
+
+    let head = repo.head().with_context("can not resolve HEAD")?;
+    // A detached `HEAD` is pinned with `dot init --ref` on purpose, it follows no branch.
+    if !head.is_branch() {
+        let head = head.peel_to_commit().with_context("can not resolve HEAD")?.id();
+        checks.push(Check::pass("dot repo up to date", format!("HEAD pinned at {:.7}", head)));
+        return Ok(checks);
+    }
+    let branch = head.shorthand().unwrap_or_default().to_owned();
+    checks.push(match fetch(prefix, &repo, "origin", &branch) {
+        Ok(fetch_commit) => {
+            let head = head.peel_to_commit().with_context("can not resolve HEAD")?.id();
+            let (ahead, behind) = repo
+                .graph_ahead_behind(head, fetch_commit.id())
+                .with_context("can not compare HEAD with origin")?;
+            if behind == 0 {
+                Check::pass("dot repo up to date", format!("{} commits ahead of origin", ahead))
+            } else {
+                let check = Check::warn(
+                    "dot repo up to date",
+                    format!("{} commits behind origin/{}", behind, branch),
+                    "run `dot init`",
+                );
+                // Pulling checkouts by force so it is only safe without local changes.
+                if changes == 0 {
+                    check.with_fix(|prefix| {
+                        let repo = Repository::open(prefix.dot())
+                            .with_context("can not open the dot repository")?;
+                        match remembered_ref(&repo) {
+                            Some(git_ref) => checkout_ref(prefix, &repo, &git_ref, true),
+                            None => pull(prefix, &repo, None, None, true),
+                        }
+                    })
+                } else {
+                    check
+                }
+            }
+        }
+        Err(e) => Check::warn(
+            "dot repo up to date",
+            format!("can not fetch origin: {}", e),
+            "check the network",
+        ),
+    });
+    Ok(checks)
+}
+
+fn run_checks(prefix: &Prefix, args: &DoctorArgs) -> Result<Vec<Check>> {
+    let mut checks = vec![
+        check_rc(prefix, args.shell, args.rc_file.as_deref())?,
+        check_path(prefix),
+        check_ssh_include(prefix)?,
+    ];
+    checks.extend(check_keys(prefix)?);
+    checks.extend(check_binaries(prefix)?);
+    checks.extend(check_repo(prefix)?);
+    Ok(checks)
+}
+
+#[derive(Serialize)]
+struct DoctorReport {
+    checks: Vec<Check>,
+}
+
+impl Report for DoctorReport {
+    fn print(&self) {
+        let width = self.checks.iter().map(|check| check.name.len()).max().unwrap_or_default();
+        println!("{:<6} {:<width$}  DETAIL", "STATUS", "CHECK");
+        for check in &self.checks {
+            println!("{:<6} {:<width$}  {}", check.status, check.name, check.detail);
+            if let Some(suggestion) = &check.suggestion {
+                println!("{:<6} {:<width$}  hint: {}", "", "", suggestion);
+            }
+        }
+    }
+}
+
+pub fn entry_doctor(prefix: &Prefix, args: DoctorArgs) -> Result<()> {
+    let mut checks = run_checks(prefix, &args)?;
+
+    if args.fix {
+        let mut fixed = false;
+        for check in checks.iter_mut() {
+            if let Some(fix) = check.fix.take() {
+                log::info!(check:% = check.name; "Fixing");
+                fix(prefix)?;
+                fixed = true;
+            }
+        }
+        if fixed {
+            checks = run_checks(prefix, &args)?;
+        }
+    }
+
+    let failed = checks.iter().filter(|check| check.status == Status::Fail).count();
+    prefix.emit(&DoctorReport { checks })?;
+    if failed > 0 {
+        return Err(Error::Check(failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_check_rc() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+
+        let check = check_rc(&prefix, Shell::Zsh, None).unwrap();
+        assert_eq!(check.status, Status::Fail);
+        check.fix.unwrap()(&prefix).unwrap();
+        assert_eq!(check_rc(&prefix, Shell::Zsh, None).unwrap().status, Status::Pass);
+
+        std::fs::write(Shell::Zsh.rc_path(&prefix, None), "export DOTDIR=/somewhere/else\n")
+            .unwrap();
+        assert_eq!(check_rc(&prefix, Shell::Zsh, None).unwrap().status, Status::Fail);
+    }
+
+    #[test]
+    fn test_check_ssh_include() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+
+        let check = check_ssh_include(&prefix).unwrap();
+        assert_eq!(check.status, Status::Fail);
+        check.fix.unwrap()(&prefix).unwrap();
+        assert_eq!(check_ssh_include(&prefix).unwrap().status, Status::Pass);
+    }
+
+    #[test]
+    fn test_check_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        SshKey::fake(&prefix, "key".into(), "hostname".into());
+        let private_path = prefix.skm().join("key").join("id_ed25519");
+        std::fs::set_permissions(&private_path, Permissions::from_mode(0o644)).unwrap();
+
+        let mut checks = check_keys(&prefix).unwrap();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Fail);
+        checks.pop().unwrap().fix.unwrap()(&prefix).unwrap();
+        assert_eq!(private_path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(check_keys(&prefix).unwrap()[0].status, Status::Pass);
+    }
+
+    #[test]
+    fn test_check_repo_detached() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let repo = Repository::init(prefix.dot()).unwrap();
+        let commit = crate::utils::commit(&repo, "dot.toml", "");
+        repo.set_head_detached(commit).unwrap();
+
+        // The pinned `HEAD` is not compared with a branch of `origin`, which does not exist.
+        let checks = check_repo(&prefix).unwrap();
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[1].status, Status::Pass);
+        assert!(checks[1].fix.is_none());
+    }
+}
@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Exit code for invalid or missing configuration.
+pub const CONFIG_EXIT_CODE: u8 = 3;
+/// Exit code for failed downloads or signature verifications.
+pub const NETWORK_EXIT_CODE: u8 = 4;
+/// Exit code for failed git operations.
+pub const GIT_EXIT_CODE: u8 = 5;
+/// Exit code for failed checks of `dot doctor`.
+pub const CHECK_EXIT_CODE: u8 = 6;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("can not access {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("can not parse {path:?}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("{0}")]
+    Config(String),
+    #[error("can not download {url}")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("can not verify signature from {url}")]
+    Signature {
+        url: String,
+        #[source]
+        source: minisign_verify::Error,
+    },
+    #[error("can not encrypt {path:?}")]
+    Encrypt {
+        path: PathBuf,
+        #[source]
+        source: Box<age::EncryptError>,
+    },
+    #[error("can not decrypt {path:?}")]
+    Decrypt {
+        path: PathBuf,
+        #[source]
+        source: Box<age::DecryptError>,
+    },
+    #[error("{context}")]
+    Git {
+        context: String,
+        #[source]
+        source: Option<git2::Error>,
+    },
+    #[error("can not run {command}")]
+    Command {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{command} exited with {status}")]
+    CommandStatus { command: String, status: ExitStatus },
+    #[error("{0} doctor checks failed")]
+    Check(usize),
+    #[error(transparent)]
+    Cli(#[from] clap::Error),
+}
+
+impl Error {
+    pub fn git(context: impl Into<String>) -> Self {
+        Self::Git { context: context.into(), source: None }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Parse { .. }
+            | Error::Config(_)
+            | Error::Encrypt { .. }
+            | Error::Decrypt { .. } => CONFIG_EXIT_CODE,
+            Error::Network { .. } | Error::Signature { .. } => NETWORK_EXIT_CODE,
+            Error::Git { .. } => GIT_EXIT_CODE,
+            Error::Check(_) => CHECK_EXIT_CODE,
+            Error::Cli(e) => e.exit_code() as _,
+            Error::Io { .. } | Error::Command { .. } | Error::CommandStatus { .. } => 1,
+        }
+    }
+}
+
+pub trait WithPath<T> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T>;
+}
+
+impl<T> WithPath<T> for Result<T, std::io::Error> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| Error::Io { path: path.as_ref().into(), source })
+    }
+}
+
+impl<T> WithPath<T> for Result<T, toml::de::Error> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| Error::Parse { path: path.as_ref().into(), source })
+    }
+}
+
+impl<T> WithPath<T> for Result<T, age::EncryptError> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| Error::Encrypt { path: path.as_ref().into(), source: source.into() })
+    }
+}
+
+impl<T> WithPath<T> for Result<T, age::DecryptError> {
+    fn with_path(self, path: impl AsRef<Path>) -> Result<T> {
+        self.map_err(|source| Error::Decrypt { path: path.as_ref().into(), source: source.into() })
+    }
+}
+
+pub trait WithUrl<T> {
+    fn with_url(self, url: impl Into<String>) -> Result<T>;
+}
+
+impl<T> WithUrl<T> for Result<T, reqwest::Error> {
+    fn with_url(self, url: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::Network { url: url.into(), source })
+    }
+}
+
+impl<T> WithUrl<T> for Result<T, minisign_verify::Error> {
+    fn with_url(self, url: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::Signature { url: url.into(), source })
+    }
+}
+
+pub trait WithContext<T> {
+    fn with_context(self, context: impl Into<String>) -> Result<T>;
+}
+
+impl<T> WithContext<T> for Result<T, git2::Error> {
+    fn with_context(self, context: impl Into<String>) -> Result<T> {
+        self.map_err(|source| Error::Git { context: context.into(), source: Some(source) })
+    }
+}
+
+/// Spawn the command, inheriting stdio, and wait for it to succeed.
+pub fn run(command: &mut std::process::Command) -> Result<()> {
+    let status = command
+        .status()
+        .map_err(|source| Error::Command { command: format!("{:?}", command), source })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::CommandStatus { command: format!("{:?}", command), status })
+    }
+}
+
+/// Run the command to completion, capturing its output, and wait for it to succeed.
+pub fn output(command: &mut std::process::Command) -> Result<Vec<u8>> {
+    let output = command
+        .output()
+        .map_err(|source| Error::Command { command: format!("{:?}", command), source })?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::CommandStatus { command: format!("{:?}", command), status: output.status })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(Error::Config("missing".into()).exit_code(), CONFIG_EXIT_CODE);
+        assert_eq!(Error::git("merge").exit_code(), GIT_EXIT_CODE);
+        assert_eq!(Error::Check(2).exit_code(), CHECK_EXIT_CODE);
+        assert_eq!(
+            toml::from_str::<toml::Table>("=").with_path("a.toml").unwrap_err().exit_code(),
+            CONFIG_EXIT_CODE
+        );
+        assert_eq!(
+            std::fs::read("/non/existent").with_path("/non/existent").unwrap_err().exit_code(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_command_status() {
+        assert!(run(&mut std::process::Command::new("true")).is_ok());
+        assert!(matches!(
+            run(&mut std::process::Command::new("false")),
+            Err(Error::CommandStatus { .. })
+        ));
+        assert_eq!(output(std::process::Command::new("echo").arg("dot")).unwrap(), b"dot\n");
+    }
+}
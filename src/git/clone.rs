@@ -1,45 +1,61 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
 use url::Url;
 
 use super::GitCloneArgs;
-use super::utils::{clone, get_default_profile};
+use super::utils::clone;
+use crate::config::DotConfig;
+use crate::error::{Error, Result, WithPath};
+use crate::output::Report;
 use crate::prefix::Prefix;
 use crate::utils::unwrap_or_missing_argument;
 
-pub fn entry_git_clone(prefix: &Prefix, args: GitCloneArgs) {
+#[derive(Debug, Serialize)]
+struct CloneReport {
+    url: String,
+    destination: PathBuf,
+}
+
+impl Report for CloneReport {}
+
+pub fn entry_git_clone(prefix: &Prefix, args: GitCloneArgs) -> Result<()> {
+    let dot_config = DotConfig::load(prefix)?;
     let repo = args.repo;
     let repo_url = if let Ok(url) = Url::parse(&repo)
         && (url.scheme() == "http" || url.scheme() == "https")
     {
         repo
     } else {
-        let config = match unwrap_or_missing_argument(
-            args.config.or_else(get_default_profile),
+        let profile = unwrap_or_missing_argument(
+            args.config.or_else(|| dot_config.default_profile()),
             "--config",
             Some("git default profile environment variable is empty"),
-        ) {
-            Ok(ok) => ok,
-            Err(e) => e.exit(),
-        };
-        assert!(
-            prefix.ssh_config().join(&config).exists(),
-            "predefined git profile does not exist"
-        );
+        )?;
+        if !prefix.ssh_config().join(&profile).exists() {
+            return Err(Error::Config(format!(
+                "predefined git profile {} does not exist",
+                profile
+            )));
+        }
 
         if repo.contains('/') {
-            format!("{}:{}", &config, &repo)
+            format!("{}:{}", &profile, &repo)
         } else {
-            format!("{}:vnghia/{}", &config, &repo)
+            format!("{}:{}/{}", &profile, &dot_config.owner, &repo)
         }
     };
 
-    let destination = args.destination.unwrap_or_else(|| {
-        std::env::current_dir()
-            .unwrap()
-            .join(repo_url.trim_end_matches(".git").split('/').next_back().unwrap())
-    });
+    let destination = match args.destination {
+        Some(destination) => destination,
+        None => std::env::current_dir()
+            .with_path(".")?
+            .join(repo_url.trim_end_matches(".git").split('/').next_back().unwrap_or_default()),
+    };
 
     log::info!(url:? = repo_url, into:? = destination; "Cloning");
-    clone(&repo_url, destination, !args.no_recursive);
+    clone(prefix, &repo_url, &destination, !args.no_recursive)?;
+    prefix.emit(&CloneReport { url: repo_url, destination })
 }
 
 #[cfg(test)]
@@ -57,7 +73,8 @@ mod tests {
             config: None,
             destination: Some(temp_dir.path().join("clone")),
             no_recursive: false,
-        });
+        })
+        .unwrap();
     }
 
     #[test]
@@ -70,7 +87,8 @@ mod tests {
             config: None,
             destination: None,
             no_recursive: false,
-        });
+        })
+        .unwrap();
         assert!(temp_dir.path().join("dotfile-rs").exists())
     }
 }
@@ -6,10 +6,11 @@ mod utils;
 use std::path::PathBuf;
 
 use clap::{Args, Subcommand};
-pub use utils::{clone, pull};
+pub use utils::{checkout_ref, clone, fetch, pull};
 
 use self::clone::entry_git_clone;
 use self::profile::entry_git_profile;
+use crate::error::Result;
 use crate::prefix::Prefix;
 use crate::utils::parse_addition;
 
@@ -72,7 +73,7 @@ pub struct GitCloneArgs {
     no_recursive: bool,
 }
 
-pub fn entry_git(prefix: &Prefix, args: GitArgs) {
+pub fn entry_git(prefix: &Prefix, args: GitArgs) -> Result<()> {
     match args.command {
         GitCommand::Profile(args) => entry_git_profile(prefix, args),
         GitCommand::Clone(args) => entry_git_clone(prefix, args),
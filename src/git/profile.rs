@@ -1,13 +1,15 @@
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
 use std::sync::OnceLock;
 
 use git2::Repository;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use super::utils::{get_default_profile, open_repo};
+use super::utils::open_repo;
 use super::{GitProfileArgs, GitProfileKeyArgs};
+use crate::config::DotConfig;
+use crate::error::{Error, Result, WithContext, WithPath};
 use crate::git::utils::convert_remote;
+use crate::output::Report;
 use crate::prefix::Prefix;
 use crate::utils::{load_predefined_and_local, unwrap_or_missing_argument};
 
@@ -29,76 +31,118 @@ pub struct GitProfile {
     config: GitConfig,
 }
 
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct RemoteChange {
+    old: String,
+    new: String,
+}
+
+/// Remote urls rewritten by a git profile, unchanged urls are omitted.
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct RemoteReport {
+    url: Option<RemoteChange>,
+    pushurl: Option<RemoteChange>,
+}
+
+impl Report for RemoteReport {}
+
 impl GitConfig {
-    fn set_config(&self, repo: &Repository) {
-        let mut config = repo.config().unwrap().open_level(git2::ConfigLevel::Local).unwrap();
+    fn set_config(&self, prefix: &Prefix, repo: &Repository) -> Result<()> {
+        let mut config = repo
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+            .with_context("can not open local git config")?;
         log::info!(name:% = self.name, email:% = self.email; "Setting user config");
-        config.set_str("user.name", &self.name).unwrap();
-        config.set_str("user.email", &self.email).unwrap();
-        for (k, v) in self.additions.iter() {
-            log::info!(k:% = k, v:% = v; "Setting git config");
-            config.set_str(k, v).unwrap();
+        let entries = [("user.name", &self.name), ("user.email", &self.email)]
+            .into_iter()
+            .chain(self.additions.iter().map(|(k, v)| (k.as_str(), v)));
+        for (k, v) in entries {
+            log::debug!(k:% = k, v:% = v; "Setting git config");
+            if !prefix.dry_run(format_args!("set git config {} = {}", k, v)) {
+                config.set_str(k, v).with_context(format!("can not set {}", k))?;
+            }
         }
+        Ok(())
     }
 }
 
 impl GitProfile {
-    pub fn load_predefined_profile(prefix: &Prefix) -> &'static HashMap<String, Self> {
+    pub fn load_predefined_profile(prefix: &Prefix) -> Result<&'static HashMap<String, Self>> {
         static PREDEFINED_CONFIG: OnceLock<HashMap<String, GitProfile>> = OnceLock::new();
-        PREDEFINED_CONFIG.get_or_init(|| {
+        PREDEFINED_CONFIG.get_or_try_init(|| {
             let configs: HashMap<String, GitConfig> =
-                load_predefined_and_local(prefix.config_git().join("profile.toml"));
-            configs
+                load_predefined_and_local(prefix.config_git().join("profile.toml"))?;
+            Ok(configs
                 .into_iter()
                 .map(|(key, config)| (key.clone(), GitProfile { key, config }))
-                .collect()
+                .collect())
         })
     }
 
-    fn extract_ssh_hostname(&self, prefix: &Prefix) -> String {
+    pub fn get_predefined_profile(prefix: &Prefix, key: &str) -> Result<&'static Self> {
+        Self::load_predefined_profile(prefix)?
+            .get(key)
+            .ok_or_else(|| Error::Config(format!("predefined git profile {} does not exist", key)))
+    }
+
+    fn extract_ssh_hostname(&self, prefix: &Prefix) -> Result<String> {
         let config_path = prefix.ssh_config().join(&self.key);
         log::debug!(path:? = config_path; "Extracting from config");
-        let mut lines = BufReader::new(std::fs::File::open(&config_path).unwrap()).lines();
+        let content = std::fs::read_to_string(&config_path).with_path(&config_path)?;
 
-        loop {
-            match lines.next() {
-                Some(line) => {
-                    let line = line.unwrap();
-                    let line = line.trim();
-                    if !line.is_empty() && !line.starts_with('#') {
-                        let (k, v) = line.split_once(' ').unwrap();
-                        log::trace!(k = k, v = v; "Extracted ssh config");
-                        if k == "Host" && v != self.key {
-                            panic!("ssh config contains invalid host value")
-                        } else if k == "Hostname" {
-                            break v.to_owned();
-                        }
-                    }
-                }
-                None => {
-                    panic!("can not extract host and hostname from ssh config")
+        for line in content.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                let (k, v) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                    Error::Config(format!("invalid line `{}` in {:?}", line, config_path))
+                })?;
+                let v = v.trim();
+                log::trace!(k = k, v = v; "Extracted ssh config");
+                if k == "Host" && v != self.key {
+                    return Err(Error::Config(format!(
+                        "ssh config {:?} contains invalid host value {}",
+                        config_path, v
+                    )));
+                } else if k == "Hostname" {
+                    return Ok(v.to_owned());
                 }
             }
         }
+        Err(Error::Config(format!("can not extract host and hostname from {:?}", config_path)))
     }
 
-    fn change_remote_url(&self, prefix: &Prefix, repo: &Repository) {
+    fn change_remote_url(&self, prefix: &Prefix, repo: &Repository) -> Result<RemoteReport> {
+        let mut report = RemoteReport::default();
         if let Ok(remote) = repo.find_remote(REMOTE_NAME) {
-            let hostname = self.extract_ssh_hostname(prefix);
+            let hostname = self.extract_ssh_hostname(prefix)?;
             log::info!(hostname:% = hostname; "Extracted hostname");
 
             if let Some(url) = remote.url() {
-                if let Some(new_url) = convert_remote(url, &self.key, &hostname) {
+                if let Some(new_url) = convert_remote(url, &self.key, &hostname)? {
                     log::info!(old:% = url, new:% = new_url; "Changing remote url");
-                    repo.remote_set_url(REMOTE_NAME, &new_url).unwrap()
+                    if !prefix.dry_run(format_args!("set remote url {} -> {}", url, new_url)) {
+                        repo.remote_set_url(REMOTE_NAME, &new_url)
+                            .with_context(format!("can not set remote url to {}", new_url))?;
+                    }
+                    report.url = Some(RemoteChange { old: url.to_owned(), new: new_url });
                 } else {
                     log::info!(url:% = url; "Remote url is already up to date");
                 }
             }
             if let Some(pushurl) = remote.pushurl() {
-                if let Some(new_pushurl) = convert_remote(pushurl, &self.key, &hostname) {
+                if let Some(new_pushurl) = convert_remote(pushurl, &self.key, &hostname)? {
                     log::info!(old:% = pushurl, new:% = new_pushurl; "Changing remote pushurl");
-                    repo.remote_set_pushurl(REMOTE_NAME, Some(pushurl)).unwrap();
+                    if !prefix
+                        .dry_run(format_args!("set remote pushurl {} -> {}", pushurl, new_pushurl))
+                    {
+                        repo.remote_set_pushurl(REMOTE_NAME, Some(&new_pushurl)).with_context(
+                            format!("can not set remote pushurl to {}", new_pushurl),
+                        )?;
+                    }
+                    report.pushurl =
+                        Some(RemoteChange { old: pushurl.to_owned(), new: new_pushurl });
                 } else {
                     log::info!(pushurl:% = pushurl; "Remote pushurl is already up to date");
                 }
@@ -106,12 +150,13 @@ impl GitProfile {
         } else {
             log::warn!(remote = REMOTE_NAME; "Remote does not exist");
         }
+        Ok(report)
     }
 
-    fn set(&self, prefix: &Prefix) {
-        let repo = open_repo(None);
-        self.config.set_config(&repo);
-        self.change_remote_url(prefix, &repo);
+    fn set(&self, prefix: &Prefix) -> Result<RemoteReport> {
+        let repo = open_repo(None)?;
+        self.config.set_config(prefix, &repo)?;
+        self.change_remote_url(prefix, &repo)
     }
 }
 
@@ -129,22 +174,23 @@ impl TryFrom<GitProfileArgs> for GitProfile {
     }
 }
 
-pub fn entry_git_profile(prefix: &Prefix, args: GitProfileKeyArgs) {
-    if let Some(config) = args.config {
-        GitProfile::load_predefined_profile(prefix).get(&config).unwrap().set(prefix)
+pub fn entry_git_profile(prefix: &Prefix, args: GitProfileKeyArgs) -> Result<()> {
+    let report = if let Some(config) = args.config {
+        GitProfile::get_predefined_profile(prefix, &config)?.set(prefix)
     } else {
         match GitProfile::try_from(args.profile) {
             Ok(profile) => profile.set(prefix),
             Err(e) => {
-                if let Some(config) = get_default_profile() {
+                if let Some(config) = DotConfig::load(prefix)?.default_profile() {
                     log::info!(config:% = config; "Use config from environment variable");
-                    GitProfile::load_predefined_profile(prefix).get(&config).unwrap().set(prefix)
+                    GitProfile::get_predefined_profile(prefix, &config)?.set(prefix)
                 } else {
-                    e.exit()
+                    Err(e.into())
                 }
             }
         }
-    }
+    }?;
+    prefix.emit(&report)
 }
 
 #[cfg(test)]
@@ -159,7 +205,7 @@ mod tests {
     fn test_extract_ssh_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         SshKey::fake(&prefix, "key".into(), "hostname".into());
         assert_eq!(
             GitProfile {
@@ -170,16 +216,45 @@ mod tests {
                     additions: Default::default()
                 },
             }
-            .extract_ssh_hostname(&prefix),
+            .extract_ssh_hostname(&prefix)
+            .unwrap(),
             "hostname"
         )
     }
 
+    #[test]
+    fn test_change_remote_url() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        SshKey::fake(&prefix, "key".into(), "hostname".into());
+        let repo = Repository::init(temp_dir.path().join("repo")).unwrap();
+        repo.remote(REMOTE_NAME, "https://hostname/username/repo.git").unwrap();
+
+        let profile = GitProfile {
+            key: "key".into(),
+            config: GitConfig {
+                name: "username".into(),
+                email: "email".into(),
+                additions: Default::default(),
+            },
+        };
+        assert_eq!(profile.change_remote_url(&prefix, &repo).unwrap(), RemoteReport {
+            url: Some(RemoteChange {
+                old: "https://hostname/username/repo.git".into(),
+                new: "key:username/repo.git".into()
+            }),
+            pushurl: None
+        });
+        assert_eq!(repo.find_remote(REMOTE_NAME).unwrap().url(), Some("key:username/repo.git"));
+        assert_eq!(profile.change_remote_url(&prefix, &repo).unwrap(), RemoteReport::default());
+    }
+
     #[test]
     fn test_parse_predefined_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         std::fs::write(
             prefix.config_git().join("profile.toml"),
             r#"
@@ -194,7 +269,7 @@ key = "value"
 "#,
         )
         .unwrap();
-        let profiles = GitProfile::load_predefined_profile(&prefix);
+        let profiles = GitProfile::load_predefined_profile(&prefix).unwrap();
         assert_eq!(profiles.get("text").unwrap(), &GitProfile {
             key: "text".into(),
             config: GitConfig {
@@ -217,7 +292,7 @@ key = "value"
     fn test_parse_predefined_and_local_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         std::fs::write(
             prefix.config_git().join("profile.toml"),
             r#"
@@ -237,7 +312,7 @@ key = "value"
 "#,
         )
         .unwrap();
-        let profiles = GitProfile::load_predefined_profile(&prefix);
+        let profiles = GitProfile::load_predefined_profile(&prefix).unwrap();
         assert_eq!(profiles.get("text").unwrap(), &GitProfile {
             key: "text".into(),
             config: GitConfig {
@@ -1,116 +1,261 @@
 use std::path::{Path, PathBuf};
 
 use git2::build::RepoBuilder;
-use git2::{FetchOptions, Repository, SubmoduleUpdateOptions};
+use git2::{AnnotatedCommit, FetchOptions, Oid, Repository, SubmoduleUpdateOptions};
 use url::Url;
 
+use crate::config::DotConfig;
+use crate::error::{Error, Result, WithContext, WithPath};
 use crate::git::progress::GitProgress;
-use crate::ssh::get_default_key;
+use crate::network::NetworkConfig;
+use crate::prefix::Prefix;
 
-pub fn clone(url: &str, path: impl AsRef<Path>, recursive: bool) -> Repository {
-    let path = path.as_ref();
-    let mut fo = FetchOptions::new();
+fn fetch_options(network: &NetworkConfig, url: &str) -> FetchOptions<'static> {
+    let mut fo = network.fetch_options(url);
     fo.remote_callbacks(GitProgress::remote_callbacks());
+    fo
+}
 
-    let repo = RepoBuilder::new().fetch_options(fo).clone(url, path).unwrap();
+pub fn clone(prefix: &Prefix, url: &str, path: impl AsRef<Path>, recursive: bool) -> Result<()> {
+    let path = path.as_ref();
+    let network = NetworkConfig::load(prefix)?;
+    let url = network.rewrite(url);
+    if prefix.dry_run(format_args!("clone {} into {:?}", url, path)) {
+        return Ok(());
+    }
+
+    let repo = RepoBuilder::new()
+        .fetch_options(fetch_options(&network, &url))
+        .clone(&url, path)
+        .with_context(format!("can not clone {} into {:?}", url, path))?;
     if recursive {
-        update_submodules(&repo)
+        update_submodules(prefix, &repo)?;
     }
-    repo
+    Ok(())
+}
+
+/// Fetch `refspecs` from `remote`, through its rewritten url if any.
+fn fetch_refspecs(
+    prefix: &Prefix,
+    repo: &Repository,
+    remote: &str,
+    refspecs: &[&str],
+) -> Result<()> {
+    let network = NetworkConfig::load(prefix)?;
+    let remote =
+        repo.find_remote(remote).with_context(format!("can not find remote {}", remote))?;
+    let url = network.rewrite(remote.url().unwrap_or_default());
+    log::info!(remote = url, refspecs:? = refspecs; "Fetching");
+
+    let mut remote = if remote.url() == Some(url.as_str()) {
+        remote
+    } else {
+        repo.remote_anonymous(&url).with_context(format!("invalid remote url {}", url))?
+    };
+    remote
+        .fetch(refspecs, Some(&mut fetch_options(&network, &url)), None)
+        .with_context(format!("can not fetch {:?} from {}", refspecs, url))
+}
+
+pub fn fetch<'r>(
+    prefix: &Prefix,
+    repo: &'r Repository,
+    remote: &str,
+    branch: &str,
+) -> Result<AnnotatedCommit<'r>> {
+    fetch_refspecs(prefix, repo, remote, &[branch])?;
+    repo.find_reference("FETCH_HEAD")
+        .and_then(|reference| repo.reference_to_annotated_commit(&reference))
+        .with_context("can not resolve FETCH_HEAD")
+}
+
+fn fast_forward(repo: &Repository, refname: &str, id: Oid) -> Result<(), git2::Error> {
+    let mut reference = repo.find_reference(refname)?;
+    reference.set_target(id, &format!("fast-forward setting {} to {}", refname, id))?;
+    repo.set_head(refname)?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
 }
 
 pub fn pull(
+    prefix: &Prefix,
     repo: &Repository,
     remote: Option<&str>,
     branch: Option<&str>,
     recursive: bool,
-) -> Result<(), git2::Error> {
+) -> Result<()> {
     let remote = remote.unwrap_or("origin");
-    let branch = branch.unwrap_or("main");
+    let default_branch;
+    let branch = match branch {
+        Some(branch) => branch,
+        None => {
+            default_branch = DotConfig::load(prefix)?.branch;
+            &default_branch
+        }
+    };
+    if prefix.dry_run(format_args!("pull {}/{} into {:?}", remote, branch, repo.workdir())) {
+        return Ok(());
+    }
 
-    let mut remote = repo.find_remote(remote)?;
-    log::info!(remote = remote.url().unwrap(), branch = branch; "Fetching");
+    let fetch_commit = fetch(prefix, repo, remote, branch)?;
+    merge(repo, branch, &fetch_commit)?;
 
-    let mut fo = FetchOptions::new();
-    fo.remote_callbacks(GitProgress::remote_callbacks());
-    remote.fetch(&[branch], Some(&mut fo), None)?;
-    let fetch_commit = repo.reference_to_annotated_commit(&repo.find_reference("FETCH_HEAD")?)?;
+    if recursive {
+        update_submodules(prefix, repo)?;
+    }
 
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
+    Ok(())
+}
+
+/// Fast-forward the local `branch` to `commit`.
+fn merge(repo: &Repository, branch: &str, commit: &AnnotatedCommit) -> Result<()> {
+    let analysis = repo.merge_analysis(&[commit]).with_context("can not analyze fetched commit")?;
     if analysis.0.is_fast_forward() {
-        log::info!(commit:% = fetch_commit.id(); "Fast-forwarding");
+        log::info!(commit:% = commit.id(); "Fast-forwarding");
         let refname = format!("refs/heads/{}", branch);
-        let mut reference = repo.find_reference(&refname)?;
-        reference.set_target(
-            fetch_commit.id(),
-            &format!("fast-forward setting {} to {}", &refname, fetch_commit.id()),
-        )?;
-        repo.set_head(&refname)?;
-        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
+        fast_forward(repo, &refname, commit.id())
+            .with_context(format!("can not fast-forward {}", refname))
     } else if analysis.0.is_up_to_date() {
-        log::info!("Already up to date")
+        log::info!("Already up to date");
+        Ok(())
     } else {
-        panic!("only fast-forward merge is allowed")
+        Err(Error::git(format!("only fast-forward merge is allowed for {}", branch)))
     }
+}
 
-    if recursive {
-        update_submodules(repo)
+/// Check out `git_ref` of `origin`: a branch is created or fast-forwarded and becomes `HEAD`,
+/// a tag or a commit is checked out as a detached `HEAD`.
+pub fn checkout_ref(
+    prefix: &Prefix,
+    repo: &Repository,
+    git_ref: &str,
+    recursive: bool,
+) -> Result<()> {
+    let remote = "origin";
+    if prefix.dry_run(format_args!("check out {} in {:?}", git_ref, repo.workdir())) {
+        return Ok(());
     }
+    let remote_refspec = format!("+refs/heads/*:refs/remotes/{}/*", remote);
+    fetch_refspecs(prefix, repo, remote, &[&remote_refspec, "+refs/tags/*:refs/tags/*"])?;
 
+    let remote_branch = format!("refs/remotes/{}/{}", remote, git_ref);
+    if let Ok(reference) = repo.find_reference(&remote_branch) {
+        let commit = repo
+            .reference_to_annotated_commit(&reference)
+            .with_context(format!("can not resolve {}", remote_branch))?;
+        let refname = format!("refs/heads/{}", git_ref);
+        if repo.find_reference(&refname).is_ok() {
+            repo.set_head(&refname)
+                .and_then(|_| {
+                    repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+                })
+                .with_context(format!("can not check out {}", refname))?;
+            merge(repo, git_ref, &commit)?;
+        } else {
+            log::info!(branch = git_ref, commit:% = commit.id(); "Creating branch");
+            repo.reference(&refname, commit.id(), false, &format!("dot: create {}", git_ref))
+                .and_then(|_| fast_forward(repo, &refname, commit.id()))
+                .with_context(format!("can not check out {}", refname))?;
+        }
+    } else {
+        let commit = repo
+            .revparse_single(&format!("refs/tags/{}", git_ref))
+            .or_else(|_| repo.revparse_single(git_ref))
+            .and_then(|object| object.peel_to_commit())
+            .with_context(format!("can not find branch, tag or commit {}", git_ref))?;
+        log::info!(git_ref = git_ref, commit:% = commit.id(); "Checking out detached");
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(git2::build::CheckoutBuilder::default().force()),
+        )
+        .and_then(|_| repo.set_head_detached(commit.id()))
+        .with_context(format!("can not check out {}", git_ref))?;
+    }
+
+    if recursive {
+        update_submodules(prefix, repo)?;
+    }
     Ok(())
 }
 
-pub fn open_repo(path: Option<PathBuf>) -> Repository {
-    let current_dir: PathBuf = path.unwrap_or_else(|| std::env::current_dir().unwrap());
+pub fn open_repo(path: Option<PathBuf>) -> Result<Repository> {
+    let current_dir = match path {
+        Some(path) => path,
+        None => std::env::current_dir().with_path(".")?,
+    };
     log::info!(dir:? = current_dir; "Opening git repository");
-    Repository::open(current_dir).unwrap()
+    Repository::open(&current_dir)
+        .with_context(format!("can not open git repository at {:?}", current_dir))
 }
 
-pub fn convert_remote(url: &str, host: &str, hostname: &str) -> Option<String> {
-    if let Ok(url) = Url::parse(url)
-        && (url.scheme() == "http" || url.scheme() == "https")
+pub fn convert_remote(url: &str, host: &str, hostname: &str) -> Result<Option<String>> {
+    if let Ok(parsed_url) = Url::parse(url)
+        && (parsed_url.scheme() == "http" || parsed_url.scheme() == "https")
     {
-        let old_host = url.host().unwrap().to_string();
+        let old_host = parsed_url.host().map(|host| host.to_string()).unwrap_or_default();
         if old_host == hostname || old_host == host {
-            Some(format!("{}:{}", host, url.path().strip_prefix('/').unwrap()))
+            Ok(Some(format!("{}:{}", host, parsed_url.path().trim_start_matches('/'))))
         } else {
-            panic!(
+            Err(Error::Config(format!(
                 "remote host ({}) does not match host ({}) or hostname ({})",
                 old_host, host, hostname
-            )
+            )))
         }
     } else {
-        let old_host = url.split_once(':').unwrap().0.split('@').next_back().unwrap();
+        let old_host = url
+            .split_once(':')
+            .ok_or_else(|| Error::Config(format!("invalid remote url {}", url)))?
+            .0
+            .split('@')
+            .next_back()
+            .unwrap_or_default();
         if old_host == host {
-            None
+            Ok(None)
         } else if old_host == hostname {
-            Some(url.replace(hostname, host))
+            Ok(Some(url.replace(hostname, host)))
         } else {
-            panic!("remote host ({}) does not match hostname ({})", old_host, hostname)
+            Err(Error::Config(format!(
+                "remote host ({}) does not match hostname ({})",
+                old_host, hostname
+            )))
         }
     }
 }
 
-pub fn get_default_profile() -> Option<String> {
-    std::env::var("DOT_GIT_DEFAULT_PROFILE").ok().or_else(get_default_key)
-}
-
-pub fn update_submodules(repo: &Repository) {
-    fn add_subrepos(repo: &Repository, list: &mut Vec<Repository>) {
-        for mut subm in repo.submodules().unwrap() {
-            let mut fo = FetchOptions::new();
-            fo.remote_callbacks(GitProgress::remote_callbacks());
-            log::info!(name:? = subm.name().unwrap(), url:? = subm.url().unwrap(); "Updating submodule");
-            subm.update(true, Some(&mut SubmoduleUpdateOptions::new().fetch(fo))).unwrap();
-            list.push(subm.open().unwrap());
+pub fn update_submodules(prefix: &Prefix, repo: &Repository) -> Result<()> {
+    fn add_subrepos(
+        network: &NetworkConfig,
+        repo: &Repository,
+        list: &mut Vec<Repository>,
+    ) -> Result<()> {
+        for mut subm in repo.submodules().with_context("can not list submodules")? {
+            let name = subm.name().unwrap_or_default().to_owned();
+            let url = network.rewrite(subm.url().unwrap_or_default());
+            log::info!(name:? = name, url:? = url; "Updating submodule");
+            if subm.url() != Some(url.as_str()) {
+                // Only the local config is rewritten so `.gitmodules` stays untouched.
+                subm.init(false)
+                    .and_then(|_| repo.config())
+                    .and_then(|mut config| config.set_str(&format!("submodule.{}.url", name), &url))
+                    .with_context(format!("can not rewrite submodule {} url", name))?;
+            }
+            subm.update(
+                true,
+                Some(&mut SubmoduleUpdateOptions::new().fetch(fetch_options(network, &url))),
+            )
+            .and_then(|_| subm.open())
+            .map(|subrepo| list.push(subrepo))
+            .with_context(format!("can not update submodule {}", name))?;
         }
+        Ok(())
     }
 
+    let network = NetworkConfig::load(prefix)?;
     let mut repos = Vec::new();
-    add_subrepos(repo, &mut repos);
+    add_subrepos(&network, repo, &mut repos)?;
     while let Some(repo) = repos.pop() {
-        add_subrepos(&repo, &mut repos);
+        add_subrepos(&network, &repo, &mut repos)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -118,11 +263,14 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
+    use crate::utils::commit;
 
     #[test]
     fn test_convert_remote_http() {
         assert_eq!(
-            convert_remote("https://git.test/username/repo.git", "host", "git.test").unwrap(),
+            convert_remote("https://git.test/username/repo.git", "host", "git.test")
+                .unwrap()
+                .unwrap(),
             "host:username/repo.git"
         )
     }
@@ -130,7 +278,7 @@ mod tests {
     #[test]
     fn test_convert_remote_http_same_host() {
         assert_eq!(
-            convert_remote("https://host/username/repo.git", "host", "git.test").unwrap(),
+            convert_remote("https://host/username/repo.git", "host", "git.test").unwrap().unwrap(),
             "host:username/repo.git"
         )
     }
@@ -138,7 +286,7 @@ mod tests {
     #[test]
     fn test_convert_remote_ssh() {
         assert_eq!(
-            convert_remote("git@git.test:username/repo.git", "host", "git.test").unwrap(),
+            convert_remote("git@git.test:username/repo.git", "host", "git.test").unwrap().unwrap(),
             "git@host:username/repo.git"
         )
     }
@@ -146,30 +294,78 @@ mod tests {
     #[test]
     fn test_convert_remote_ssh_no_user() {
         assert_eq!(
-            convert_remote("git.test:username/repo.git", "host", "git.test").unwrap(),
+            convert_remote("git.test:username/repo.git", "host", "git.test").unwrap().unwrap(),
             "host:username/repo.git"
         )
     }
 
     #[test]
     fn test_convert_remote_ssh_same_host() {
-        assert!(convert_remote("git@host:username/repo.git", "host", "git.test").is_none())
+        assert!(convert_remote("git@host:username/repo.git", "host", "git.test").unwrap().is_none())
     }
 
     #[test]
     fn test_convert_remote_ssh_same_host_no_user() {
-        assert!(convert_remote("host:username/repo.git", "host", "git.test").is_none())
+        assert!(convert_remote("host:username/repo.git", "host", "git.test").unwrap().is_none())
+    }
+
+    #[test]
+    fn test_convert_remote_mismatch() {
+        assert!(matches!(
+            convert_remote("git@other:username/repo.git", "host", "git.test"),
+            Err(Error::Config(_))
+        ))
+    }
+
+    #[test]
+    fn test_checkout_ref() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let upstream = Repository::init(temp_dir.path().join("upstream")).unwrap();
+        let first = commit(&upstream, "first", "first");
+        upstream.tag_lightweight("v1", &upstream.find_object(first, None).unwrap(), false).unwrap();
+        upstream.branch("dev", &upstream.find_commit(first).unwrap(), false).unwrap();
+        let second = commit(&upstream, "second", "second");
+
+        let path = temp_dir.path().join("clone");
+        clone(&prefix, upstream.path().to_str().unwrap(), &path, false).unwrap();
+        let repo = Repository::open(&path).unwrap();
+
+        checkout_ref(&prefix, &repo, "v1", false).unwrap();
+        assert!(repo.head_detached().unwrap());
+        assert_eq!(repo.head().unwrap().target(), Some(first));
+        assert!(!path.join("second").exists());
+
+        checkout_ref(&prefix, &repo, "dev", false).unwrap();
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/dev"));
+
+        checkout_ref(&prefix, &repo, &second.to_string(), false).unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(second));
     }
 
     #[test]
     fn test_clone() {
         let temp_dir = TempDir::new().unwrap();
-        clone("https://github.com/vnghia/dotfile-rs.git", temp_dir.path().join("clone"), false);
+        let prefix: Prefix = (&temp_dir).into();
+        clone(
+            &prefix,
+            "https://github.com/vnghia/dotfile-rs.git",
+            temp_dir.path().join("clone"),
+            false,
+        )
+        .unwrap();
     }
 
     #[test]
     fn test_clone_recursive() {
         let temp_dir = TempDir::new().unwrap();
-        clone("https://github.com/vnghia/dotfile-rs.git", temp_dir.path().join("clone"), true);
+        let prefix: Prefix = (&temp_dir).into();
+        clone(
+            &prefix,
+            "https://github.com/vnghia/dotfile-rs.git",
+            temp_dir.path().join("clone"),
+            true,
+        )
+        .unwrap();
     }
 }
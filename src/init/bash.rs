@@ -0,0 +1,56 @@
+use std::path::Path;
+
+use itertools::Itertools;
+
+pub fn bashrc_content(
+    dot_dir: impl AsRef<Path>,
+    code_dir: impl AsRef<Path>,
+    local_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+) -> String {
+    let dot_dir = dot_dir.as_ref();
+    let code_dir = code_dir.as_ref();
+    let local_dir = local_dir.as_ref();
+    let bin_dir = bin_dir.as_ref();
+
+    let shell_dir = dot_dir.join("shell");
+    let sh_dir = shell_dir.join("common");
+    let bash_dir = shell_dir.join("bash");
+
+    let export_paths = [
+        ("DOTDIR", dot_dir),
+        ("CODEDIR", code_dir),
+        ("LOCALDIR", local_dir),
+        ("BINDIR", bin_dir),
+        ("SHDIR", &sh_dir),
+        ("BASHDIR", &bash_dir),
+    ]
+    .into_iter()
+    .map(|(var, path)| format!("export {}={}", var, path.to_str().unwrap()))
+    .collect_vec();
+
+    [
+        export_paths.as_slice(),
+        ["".to_string(), "source \"$BASHDIR/bashrc.bash\"".to_string(), "".to_string()].as_slice(),
+    ]
+    .concat()
+    .join("\n")
+}
+
+/// The login file of bash, which only reads the first existing one of these.
+pub const LOGIN_FILES: [&str; 3] = [".bash_profile", ".bash_login", ".profile"];
+
+/// Login shells do not read `.bashrc` so the login file sources the generated rc file. A new
+/// `.bash_profile` also sources `.profile`, which bash skips once `.bash_profile` exists.
+pub fn bash_profile_content(login_file: &str, rc_path: impl AsRef<Path>) -> String {
+    let rc_path = rc_path.as_ref().to_str().unwrap();
+    let source_rc = format!("[ -f {} ] && source {}\n", rc_path, rc_path);
+    match login_file {
+        ".bash_profile" => {
+            format!("[ -f \"$HOME/.profile\" ] && . \"$HOME/.profile\"\n{}", source_rc)
+        }
+        // `.profile` is also read by the other sh shells.
+        ".profile" => format!("[ -n \"$BASH_VERSION\" ] && {}", source_rc),
+        _ => source_rc,
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Result, WithPath};
+use crate::prefix::Prefix;
+
+const BLOCK_START: &str = "# >>> dot >>>";
+const BLOCK_END: &str = "# <<< dot <<<";
+/// First line of the rc files which older versions of `dot init` wrote as a whole.
+const LEGACY_HEADER: &str = "# AUTO GENERATED FILE. DO NOT EDIT";
+
+fn block(content: &str) -> String {
+    format!(
+        "{}\n# Managed by `dot init`, changes inside this block are overwritten.\n{}{}\n",
+        BLOCK_START, content, BLOCK_END
+    )
+}
+
+/// Byte range of the managed block, including the end marker line.
+fn block_range(current: &str) -> Option<std::ops::Range<usize>> {
+    let start = current.find(BLOCK_START)?;
+    let end = start + current[start..].find(BLOCK_END)? + BLOCK_END.len();
+    let end = if current[end..].starts_with('\n') { end + 1 } else { end };
+    Some(start..end)
+}
+
+/// Whether `current` contains a managed block with exactly `content`.
+pub fn contains(current: &str, content: &str) -> bool {
+    block_range(current).is_some_and(|range| current[range] == block(content))
+}
+
+/// Whether `current` contains a managed block.
+pub fn has_block(current: &str) -> bool {
+    block_range(current).is_some()
+}
+
+/// `current` without its managed block and the blank line separating it from the rest.
+fn strip(current: &str) -> String {
+    let Some(range) = block_range(current) else {
+        return current.to_owned();
+    };
+    let before = &current[..range.start];
+    let before =
+        before.strip_suffix('\n').filter(|before| before.ends_with('\n')).unwrap_or(before);
+    format!("{}{}", before, &current[range.end..])
+}
+
+/// Replace the managed block of `current` with `content` or append a new one.
+/// A file generated as a whole by an older version is replaced by the block.
+fn update(current: &str, content: &str) -> String {
+    if let Some(range) = block_range(current) {
+        let mut updated = current.to_owned();
+        updated.replace_range(range, &block(content));
+        updated
+    } else if current.is_empty() || current.starts_with(LEGACY_HEADER) {
+        block(content)
+    } else {
+        let separator = if current.ends_with('\n') { "\n" } else { "\n\n" };
+        format!("{}{}{}", current, separator, block(content))
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let timestamp =
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(format!(".dot-backup.{}", timestamp));
+    path.with_file_name(file_name)
+}
+
+/// Write `content` into the managed block of `path`, leaving the rest of the file untouched.
+/// A file is backed up the first time a block is added to it.
+pub fn write(prefix: &Prefix, path: &Path, content: &str) -> Result<()> {
+    let current =
+        if path.exists() { std::fs::read_to_string(path).with_path(path)? } else { String::new() };
+    let updated = update(&current, content);
+    if updated == current {
+        log::debug!(path:? = path; "Managed block is up to date");
+        return Ok(());
+    }
+    if prefix.dry_run(format_args!("update managed block of {:?}:\n{}", path, block(content))) {
+        return Ok(());
+    }
+
+    if !current.is_empty() && block_range(&current).is_none() {
+        let backup_path = backup_path(path);
+        log::info!(path:? = path, backup:? = backup_path; "Backing up file");
+        std::fs::copy(path, &backup_path).with_path(&backup_path)?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_path(dir)?;
+    }
+    log::debug!(path:? = path; "Updating managed block");
+    log::trace!(content:% = content; "Updating managed block");
+    std::fs::write(path, updated).with_path(path)
+}
+
+/// Remove the managed block of `path`, the whole file if nothing else is left.
+pub fn remove(prefix: &Prefix, path: &Path) -> Result<()> {
+    let current = std::fs::read_to_string(path).with_path(path)?;
+    let stripped = strip(&current);
+    if stripped == current {
+        return Ok(());
+    }
+    if stripped.trim().is_empty() {
+        if !prefix.dry_run(format_args!("remove {:?}", path)) {
+            log::info!(path:? = path; "Removing generated file");
+            std::fs::remove_file(path).with_path(path)?;
+        }
+    } else if !prefix.dry_run(format_args!("remove managed block of {:?}", path)) {
+        log::info!(path:? = path; "Removing managed block");
+        std::fs::write(path, stripped).with_path(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        assert_eq!(update("", "a\n"), block("a\n"));
+        let current = format!("before\n{}after\n", block("a\n"));
+        assert_eq!(update(&current, "b\n"), format!("before\n{}after\n", block("b\n")));
+        assert_eq!(update("before", "a\n"), format!("before\n\n{}", block("a\n")));
+        assert!(contains(&update(&current, "b\n"), "b\n"));
+        assert!(!contains(&current, "b\n"));
+        assert_eq!(strip(&update("before\n", "a\n")), "before\n");
+        assert_eq!(strip(&current), "before\nafter\n");
+        assert_eq!(strip(&block("a\n")), "");
+    }
+
+    #[test]
+    fn test_update_legacy() {
+        let legacy = format!("{}\n\nexport DOTDIR=/old\n", LEGACY_HEADER);
+        assert_eq!(update(&legacy, "export DOTDIR=/dot\n"), block("export DOTDIR=/dot\n"));
+    }
+
+    #[test]
+    fn test_write_backup_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let path = temp_dir.path().join(".zshenv");
+        std::fs::write(&path, "export CONDA=1\n").unwrap();
+
+        write(&prefix, &path, "export DOTDIR=/dot\n").unwrap();
+        write(&prefix, &path, "export DOTDIR=/dot\n").unwrap();
+        write(&prefix, &path, "export DOTDIR=/other\n").unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("export CONDA=1\n"));
+        assert!(contains(&content, "export DOTDIR=/other\n"));
+        let backups: Vec<_> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with(".zshenv.dot-backup."))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join(&backups[0])).unwrap(),
+            "export CONDA=1\n"
+        );
+    }
+}
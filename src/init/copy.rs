@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+use crate::error::{Result, WithPath};
+
+/// Ignore file of the dot repository applied on top of `.gitignore` when copying.
+const DOTIGNORE: &str = ".dotignore";
+
+/// Walk `root` without `.git` and `excluded`, skipping what `.gitignore` and `.dotignore`
+/// ignore.
+fn walk(root: &Path, excluded: &[PathBuf]) -> Result<Vec<(PathBuf, std::fs::FileType)>> {
+    let excluded: Vec<_> = excluded.iter().map(|path| root.join(path)).collect();
+    let mut entries = vec![];
+    for entry in WalkBuilder::new(root)
+        .hidden(false)
+        .parents(false)
+        .git_global(false)
+        .require_git(false)
+        .follow_links(false)
+        .add_custom_ignore_filename(DOTIGNORE)
+        .filter_entry(move |entry| {
+            entry.file_name() != ".git" && !excluded.iter().any(|path| path == entry.path())
+        })
+        .build()
+    {
+        let entry = entry.map_err(std::io::Error::other).with_path(root)?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap().to_owned();
+        let file_type = entry.file_type().unwrap();
+        entries.push((relative, file_type));
+    }
+    Ok(entries)
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path).with_path(path),
+        Ok(_) => std::fs::remove_file(path).with_path(path),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Make `destination` match `source`: symlinks are recreated instead of followed, modes are kept
+/// and files which are no longer in `source` are removed. Ignored files of `destination` such as
+/// rendered templates or decrypted secrets are left untouched.
+/// `.git` and `excluded`, relative to both directories, are never copied nor removed whatever
+/// the ignore files say.
+pub fn mirror(source: &Path, destination: &Path, excluded: &[PathBuf]) -> Result<()> {
+    std::fs::create_dir_all(destination).with_path(destination)?;
+    let entries = walk(source, excluded)?;
+
+    for (relative, file_type) in &entries {
+        let (from, to) = (source.join(relative), destination.join(relative));
+        let existing = std::fs::symlink_metadata(&to).ok();
+        if file_type.is_symlink() {
+            let target = std::fs::read_link(&from).with_path(&from)?;
+            if std::fs::read_link(&to).is_ok_and(|current| current == target) {
+                continue;
+            }
+            remove(&to)?;
+            symlink(&target, &to).with_path(&to)?;
+        } else if file_type.is_dir() {
+            if existing.is_some_and(|metadata| !metadata.is_dir()) {
+                remove(&to)?;
+            }
+            std::fs::create_dir_all(&to).with_path(&to)?;
+            let permissions = std::fs::metadata(&from).with_path(&from)?.permissions();
+            std::fs::set_permissions(&to, permissions).with_path(&to)?;
+        } else {
+            if existing.is_some_and(|metadata| !metadata.is_file()) {
+                remove(&to)?;
+            }
+            // `std::fs::copy` also copies the permission bits.
+            std::fs::copy(&from, &to).with_path(&from)?;
+        }
+    }
+
+    let copied: HashSet<_> = entries.into_iter().map(|(relative, _)| relative).collect();
+    let mut removed: Vec<PathBuf> = vec![];
+    for (relative, _) in walk(destination, excluded)? {
+        if !copied.contains(&relative) && !removed.iter().any(|dir| relative.starts_with(dir)) {
+            log::debug!(path:? = relative; "Removing file deleted from the source");
+            remove(&destination.join(&relative))?;
+            removed.push(relative);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_mirror() {
+        let temp_dir = TempDir::new().unwrap();
+        let (source, destination) = (temp_dir.path().join("source"), temp_dir.path().join("dot"));
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::create_dir_all(source.join("target")).unwrap();
+        std::fs::write(source.join(".gitignore"), "target/\n/rendered\n").unwrap();
+        std::fs::write(source.join(".dotignore"), "*.bak\n").unwrap();
+        std::fs::write(source.join("bin").join("run"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(source.join("bin").join("run"), PermissionsExt::from_mode(0o755))
+            .unwrap();
+        std::fs::write(source.join("target").join("dot"), "").unwrap();
+        std::fs::write(source.join("notes.bak"), "").unwrap();
+        symlink("bin/run", source.join("run")).unwrap();
+
+        std::fs::create_dir_all(destination.join("stale")).unwrap();
+        std::fs::write(destination.join("stale").join("file"), "").unwrap();
+        std::fs::write(destination.join("rendered"), "").unwrap();
+        std::fs::create_dir_all(destination.join(".local").join("skm")).unwrap();
+        std::fs::write(destination.join(".local").join("skm").join("id_ed25519"), "").unwrap();
+        std::fs::create_dir_all(source.join(".local")).unwrap();
+        std::fs::write(source.join(".local").join("history"), "").unwrap();
+
+        mirror(&source, &destination, &[PathBuf::from(".local")]).unwrap();
+        let mode = std::fs::metadata(destination.join("bin").join("run")).unwrap().permissions();
+        assert_eq!(mode.mode() & 0o777, 0o755);
+        assert_eq!(std::fs::read_link(destination.join("run")).unwrap(), Path::new("bin/run"));
+        assert!(!destination.join("target").exists());
+        assert!(!destination.join("notes.bak").exists());
+        assert!(!destination.join("stale").exists());
+        assert!(destination.join("rendered").exists());
+        assert!(destination.join(".local").join("skm").join("id_ed25519").exists());
+        assert!(!destination.join(".local").join("history").exists());
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use itertools::Itertools;
+
+/// Files of `shell/common` translated into fish, in the order they are sourced.
+const TRANSLATED_FILES: [&str; 3] = ["directory.sh", "path.sh", "config.sh"];
+
+/// Translate the `export` lines of a POSIX shell file into `set -gx`.
+/// Comments are kept and any other line is dropped.
+fn translate_sh(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if line.is_empty() || line.starts_with('#') {
+                Some(line.to_owned())
+            } else {
+                let (var, value) = line.strip_prefix("export ")?.split_once('=')?;
+                if var == "PATH" {
+                    // Fish keeps `$PATH` as a list instead of a colon separated string.
+                    let paths = value
+                        .trim_matches('"')
+                        .split(':')
+                        .map(|path| {
+                            if path == "$PATH" { path.to_owned() } else { format!("\"{}\"", path) }
+                        })
+                        .join(" ");
+                    Some(format!("set -gx PATH {}", paths))
+                } else {
+                    Some(format!("set -gx {} {}", var, value))
+                }
+            }
+        })
+        .collect()
+}
+
+/// `source_dot_dir` is where the files to translate are read, `dot_dir` may be relative to
+/// `$HOME` instead.
+pub fn dot_fish_content(
+    dot_dir: impl AsRef<Path>,
+    code_dir: impl AsRef<Path>,
+    local_dir: impl AsRef<Path>,
+    bin_dir: impl AsRef<Path>,
+    source_dot_dir: impl AsRef<Path>,
+) -> String {
+    let dot_dir = dot_dir.as_ref();
+    let code_dir = code_dir.as_ref();
+    let local_dir = local_dir.as_ref();
+    let bin_dir = bin_dir.as_ref();
+
+    let sh_dir = dot_dir.join("shell").join("common");
+
+    let export_paths = [
+        ("DOTDIR", dot_dir),
+        ("CODEDIR", code_dir),
+        ("LOCALDIR", local_dir),
+        ("BINDIR", bin_dir),
+        ("SHDIR", &sh_dir),
+    ]
+    .into_iter()
+    .map(|(var, path)| format!("set -gx {} {}", var, path.to_str().unwrap()))
+    .collect_vec();
+
+    let translated = TRANSLATED_FILES
+        .into_iter()
+        .filter_map(|name| {
+            let path = source_dot_dir.as_ref().join("shell").join("common").join(name);
+            let content = std::fs::read_to_string(path).ok()?;
+            Some(
+                [
+                    vec!["".to_string(), format!("# Translated from {}", name)],
+                    translate_sh(&content),
+                ]
+                .concat(),
+            )
+        })
+        .concat();
+
+    [export_paths.as_slice(), translated.as_slice(), ["".to_string()].as_slice()]
+        .concat()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_sh() {
+        assert_eq!(
+            translate_sh(
+                "# Editor\nexport VISUAL=\"code --wait\"\n\nexport PATH=\"$BINDIR:$PATH\"\nalias \
+                 ls=\"ls\"\n"
+            ),
+            ["# Editor", "set -gx VISUAL \"code --wait\"", "", "set -gx PATH \"$BINDIR\" $PATH"]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::error::{Result, WithPath};
+use crate::install::InstallConfig;
+use crate::prefix::Prefix;
+use crate::{bootstrap, link};
+
+/// Copy `from` to `to` recursively, keeping the symlinks and the permissions, then remove
+/// `from`. Used instead of a rename when `to` is on another file system.
+fn copy_and_remove(from: &Path, to: &Path) -> std::io::Result<()> {
+    fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+        let metadata = std::fs::symlink_metadata(from)?;
+        if metadata.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+        } else if metadata.is_dir() {
+            std::fs::create_dir(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                copy(&entry.path(), &to.join(entry.file_name()))?;
+            }
+            std::fs::set_permissions(to, metadata.permissions())
+        } else {
+            std::fs::copy(from, to).map(|_| ())
+        }
+    }
+
+    let is_dir = std::fs::symlink_metadata(from)?.is_dir();
+    let remove =
+        |path| if is_dir { std::fs::remove_dir_all(path) } else { std::fs::remove_file(path) };
+    if let Err(e) = copy(from, to) {
+        // Leave `from` complete rather than half of it on each side.
+        remove(to).ok();
+        return Err(e);
+    }
+    remove(from)
+}
+
+/// Move `from` to `to`, merging its entries into `to` if it is an existing directory.
+/// Entries already existing in `to` are left in `from`.
+fn move_path(prefix: &Prefix, from: &Path, to: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(from).with_path(from)?;
+    match std::fs::symlink_metadata(to) {
+        Err(_) => {
+            if prefix.dry_run(format_args!("move {:?} to {:?}", from, to)) {
+                return Ok(());
+            }
+            log::debug!(from:? = from, to:? = to; "Moving");
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            match std::fs::rename(from, to) {
+                Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                    log::debug!(from:? = from, to:? = to; "Copying across file systems");
+                    copy_and_remove(from, to).with_path(from)
+                }
+                result => result.with_path(from),
+            }
+        }
+        Ok(existing) if existing.is_dir() && metadata.is_dir() => {
+            for entry in std::fs::read_dir(from).with_path(from)? {
+                let entry = entry.with_path(from)?;
+                move_path(prefix, &entry.path(), &to.join(entry.file_name()))?;
+            }
+            if !prefix.is_dry_run() && std::fs::remove_dir(from).is_err() {
+                log::warn!(path:? = from; "Directory is not empty after the migration");
+            }
+            Ok(())
+        }
+        Ok(_) => {
+            log::warn!(from:? = from, to:? = to; "Destination already exists, skipping");
+            Ok(())
+        }
+    }
+}
+
+/// Move the dot environment of `from` into the directories of `to`, the innermost directories
+/// first as the legacy layout nests everything inside the dot repository.
+/// `to` may use another layout, another root or both. Return the moved paths.
+pub fn migrate(from: &Prefix, to: &Prefix) -> Result<Vec<(PathBuf, PathBuf)>> {
+    log::info!(
+        from:? = from.prefix(), from_layout:? = from.layout(),
+        to:? = to.prefix(), to_layout:? = to.layout();
+        "Migrating"
+    );
+    // `~/.local/bin` is shared with other programs so only the known binaries are moved.
+    let mut moves: Vec<_> = InstallConfig::value_variants()
+        .iter()
+        .map(|config| (from.bin().join(config.name()), to.bin().join(config.name())))
+        .collect();
+    moves.extend([
+        (link::state_path(from), link::state_path(to)),
+        (bootstrap::state_path(from), bootstrap::state_path(to)),
+        (from.hook(), to.hook()),
+        (from.skm(), to.skm()),
+        (from.completion(), to.completion()),
+        (from.plugin(), to.plugin()),
+        (from.local(), to.local()),
+        (from.dot(), to.dot()),
+        // Only differs when the root changes.
+        (from.ssh_config(), to.ssh_config()),
+    ]);
+    moves.retain(|(from, to)| from != to && std::fs::symlink_metadata(from).is_ok());
+
+    for (from_path, to_path) in &moves {
+        move_path(from, from_path, to_path)?;
+    }
+    for dir in [from.state(), from.cache()] {
+        if dir != from.local() && !from.is_dry_run() {
+            // Only removed if nothing else was left inside.
+            std::fs::remove_dir(&dir).ok();
+        }
+    }
+    Ok(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::prefix::Layout;
+
+    #[test]
+    fn test_copy_and_remove() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = temp_dir.path().join("from");
+        std::fs::create_dir_all(from.join("bin")).unwrap();
+        std::fs::write(from.join("bin").join("tool"), "tool").unwrap();
+        std::os::unix::fs::symlink("bin/tool", from.join("tool")).unwrap();
+
+        let to = temp_dir.path().join("to");
+        copy_and_remove(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(to.join("bin").join("tool")).unwrap(), "tool");
+        assert_eq!(std::fs::read_link(to.join("tool")).unwrap(), Path::new("bin/tool"));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy: Prefix = (&temp_dir).into();
+        legacy.create_dir_all().unwrap();
+        std::fs::write(legacy.config().join("dot.toml"), "layout = \"xdg\"\n").unwrap();
+        std::fs::write(legacy.bin().join("starship"), "").unwrap();
+        std::fs::write(legacy.hook().join("starship.zsh"), "").unwrap();
+        std::fs::write(legacy.local().join(".zsh_history"), "").unwrap();
+        std::fs::write(link::state_path(&legacy), "").unwrap();
+        let other = temp_dir.path().join(".local").join("bin").join("other");
+        std::fs::create_dir_all(other.parent().unwrap()).unwrap();
+        std::fs::write(&other, "").unwrap();
+
+        let xdg = legacy.to_layout(Layout::Xdg);
+        let moves = migrate(&legacy, &xdg).unwrap();
+        assert!(moves.contains(&(legacy.dot(), temp_dir.path().join(".config").join("dot"))));
+        assert!(!legacy.dot().exists());
+        assert!(xdg.config().join("dot.toml").exists());
+        assert!(temp_dir.path().join(".local").join("bin").join("starship").exists());
+        assert!(other.exists());
+        assert!(xdg.hook().join("starship.zsh").starts_with(temp_dir.path().join(".cache")));
+        assert!(xdg.hook().join("starship.zsh").exists());
+        assert!(xdg.local().join(".zsh_history").exists());
+        assert!(link::state_path(&xdg).starts_with(temp_dir.path().join(".local").join("state")));
+        assert!(link::state_path(&xdg).exists());
+        assert_eq!(Prefix::from(&temp_dir).layout(), Layout::Xdg);
+
+        migrate(&xdg, &legacy).unwrap();
+        assert!(legacy.config().join("dot.toml").exists());
+        assert!(legacy.bin().join("starship").exists());
+        assert!(legacy.local().join(".zsh_history").exists());
+        assert!(!xdg.dot().exists());
+        assert!(other.exists());
+    }
+}
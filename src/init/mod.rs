@@ -1,94 +1,336 @@
+mod bash;
+pub mod block;
+mod copy;
+mod fish;
+mod migrate;
 mod zsh;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::{Args, CommandFactory, ValueEnum};
 use git2::Repository;
+use serde::Serialize;
 
-use crate::Cli;
-use crate::git::{clone, pull};
-use crate::prefix::Prefix;
+pub use self::migrate::migrate;
+use crate::bootstrap::{BootstrapReport, bootstrap};
+use crate::completion::install_zsh_completion;
+use crate::config::DotConfig;
+use crate::error::{Result, WithContext, WithPath};
+use crate::git::{checkout_ref, clone, pull};
+use crate::link::{link, relocate};
+use crate::output::Report;
+use crate::prefix::{Prefix, XdgDir};
+use crate::secret::{decrypt_all, identities, secret_files};
+use crate::status::outdated_ssh_configs;
+use crate::template::render_all;
+use crate::{Cli, plugin};
 
 #[derive(Debug, Args)]
 pub struct InitArgs {
-    /// Url of the dot git repository.
-    #[arg(short, long, value_enum, default_value = "https://github.com/vnghia/dot.git")]
-    pub repo: String,
-    /// Copy from the repo instead of cloning.
+    /// Url of the dot git repository. Default to `repo` of `dot.toml`, which is read from
+    /// `$XDG_CONFIG_HOME/dot.toml` before the first clone.
+    #[arg(short, long)]
+    pub repo: Option<String>,
+    /// Branch, tag or commit of the dot repository to check out.
+    /// Remembered in the repository config: later inits follow the branch or stay pinned.
+    #[arg(long = "ref", conflicts_with = "copy")]
+    pub git_ref: Option<String>,
+    /// Copy from the repo instead of cloning, skipping what `.gitignore` and `.dotignore` ignore.
     #[arg(short, long, default_value_t = false)]
     pub copy: bool,
     /// The corresponding shell to initialize dotfile environments.
     #[arg(short, long, value_enum, default_value_t = Shell::Zsh)]
     pub shell: Shell,
-    /// Custom .bashrc/.zshenv in case of read-only default rc file.
+    /// Custom .bashrc/.zshenv/dot.fish in case of read-only default rc file.
     #[arg(long)]
     pub rc_file: Option<String>,
+    /// Run `config/bootstrap.toml` afterwards, skipping the tasks done by a previous run.
+    #[arg(long)]
+    pub bootstrap: bool,
+    /// Move files conflicting with the link map into the dot repository.
+    #[arg(long)]
+    pub adopt: bool,
 }
 
+/// How the generated rc files refer to the home directory, so that they survive moving it.
+const HOME: &str = "$HOME";
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum Shell {
     Zsh,
+    Bash,
+    Fish,
 }
 
-fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
-    if dst.as_ref().file_name().unwrap() != "target" {
-        std::fs::create_dir_all(&dst)?;
-        for entry in std::fs::read_dir(src)? {
-            let entry = entry?;
-            let ty = entry.file_type()?;
-            if ty.is_dir() {
-                copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
-            } else {
-                std::fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
-            }
+impl Shell {
+    pub fn rc_path(self, prefix: &Prefix, rc_file: Option<&str>) -> PathBuf {
+        match self {
+            Shell::Zsh => prefix.prefix().join(rc_file.unwrap_or(".zshenv")),
+            Shell::Bash => prefix.prefix().join(rc_file.unwrap_or(".bashrc")),
+            Shell::Fish => match rc_file {
+                Some(rc_file) => prefix.prefix().join(rc_file),
+                None => prefix.xdg(XdgDir::Config).join("fish").join("conf.d").join("dot.fish"),
+            },
+        }
+    }
+
+    fn rc_content(self, prefix: &Prefix) -> String {
+        let [dot, code, local, bin, hook] =
+            [prefix.dot(), prefix.code(), prefix.local(), prefix.bin(), prefix.hook()]
+                .map(|path| prefix.home_relative(&path, HOME));
+        match self {
+            Shell::Zsh => zsh::zshenv_content(dot, code, local, bin, hook),
+            Shell::Bash => bash::bashrc_content(dot, code, local, bin),
+            Shell::Fish => fish::dot_fish_content(dot, code, local, bin, prefix.dot()),
+        }
+    }
+
+    /// Every file generated for this shell with its content, the rc file comes first.
+    fn rc_files(self, prefix: &Prefix, rc_file: Option<&str>) -> Vec<(PathBuf, String)> {
+        let rc_path = self.rc_path(prefix, rc_file);
+        let mut files = vec![(rc_path.clone(), self.rc_content(prefix))];
+        if self == Shell::Bash {
+            let login_file = bash::LOGIN_FILES
+                .into_iter()
+                .find(|file| prefix.prefix().join(file).exists())
+                .unwrap_or(bash::LOGIN_FILES[0]);
+            files.push((
+                prefix.prefix().join(login_file),
+                bash::bash_profile_content(login_file, prefix.home_relative(&rc_path, HOME)),
+            ));
+        }
+        files
+    }
+
+    /// Default paths of every file generated for this shell.
+    pub fn rc_paths(self, prefix: &Prefix) -> Vec<PathBuf> {
+        self.rc_files(prefix, None).into_iter().map(|(path, _)| path).collect()
+    }
+
+    pub fn generate_rc(self, prefix: &Prefix, rc_file: Option<&str>) -> Result<()> {
+        for (path, content) in self.rc_files(prefix, rc_file) {
+            block::write(prefix, &path, &content)?;
         }
+        Ok(())
+    }
+
+    /// Whether the rc file contains the up to date managed block.
+    pub fn is_rc_up_to_date(self, prefix: &Prefix, current: &str) -> bool {
+        block::contains(current, &self.rc_content(prefix))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct InitReport {
+    repo: String,
+    dot: PathBuf,
+    rc_file: PathBuf,
+    bin: PathBuf,
+    /// Paths moved to follow the `layout` of `dot.toml`.
+    moved: Vec<(PathBuf, PathBuf)>,
+    bootstrap: Option<BootstrapReport>,
+}
+
+impl Report for InitReport {}
+
+/// Key of the dot repository config holding the ref passed to `dot init --ref`.
+const REF_CONFIG_KEY: &str = "dot.ref";
+
+pub fn remembered_ref(repo: &Repository) -> Option<String> {
+    repo.config().and_then(|config| config.get_string(REF_CONFIG_KEY)).ok()
+}
+
+pub fn remember_ref(prefix: &Prefix, repo: &Repository, git_ref: &str) -> Result<()> {
+    if remembered_ref(repo).as_deref() == Some(git_ref)
+        || prefix.dry_run(format_args!("set git config {} = {}", REF_CONFIG_KEY, git_ref))
+    {
+        return Ok(());
     }
-    Ok(())
+    repo.config()
+        .and_then(|mut config| config.set_str(REF_CONFIG_KEY, git_ref))
+        .with_context(format!("can not remember ref {}", git_ref))
 }
 
-pub fn entry_init(prefix: &Prefix, args: InitArgs) {
+pub fn entry_init(prefix: &Prefix, args: InitArgs) -> Result<()> {
     let dot_dir = prefix.dot();
     let code_dir = prefix.code();
-    let local_dir = prefix.local();
-    let bin_dir = prefix.bin();
     log::info!(dot:? = dot_dir, code:? = code_dir; "Directory");
+    let repo_url = match args.repo {
+        Some(repo) => repo,
+        None => DotConfig::load(prefix)?.repo,
+    };
 
     if args.copy {
-        let Some(repo) = args.repo.strip_prefix("file://") else {
-            Cli::command()
+        let Some(repo) = repo_url.strip_prefix("file://") else {
+            return Err(Cli::command()
                 .error(
                     clap::error::ErrorKind::InvalidValue,
                     "--copy can only be used with repo start with `file://`",
                 )
-                .exit()
+                .into());
         };
         log::info!(repo = repo, dest:? = dot_dir; "Copying dot repository");
-        std::fs::remove_dir_all(dot_dir.join(".git")).ok();
-        copy_dir_all(repo, &dot_dir).unwrap();
+        if !prefix.dry_run(format_args!("copy {:?} into {:?}", repo, dot_dir)) {
+            std::fs::remove_dir_all(dot_dir.join(".git")).ok();
+            // The legacy layout keeps the binaries and the ssh keys inside the dot directory.
+            let excluded: Vec<_> = prefix
+                .local()
+                .strip_prefix(&dot_dir)
+                .map(|local| local.to_owned())
+                .into_iter()
+                .collect();
+            copy::mirror(Path::new(repo), &dot_dir, &excluded)?;
+        }
     } else if dot_dir.exists() {
-        log::info!(repo:? = args.repo, dest:? = dot_dir; "Opening existing dot repository");
-        pull(&Repository::open(&dot_dir).unwrap(), None, None, true).unwrap();
+        log::info!(repo:? = repo_url, dest:? = dot_dir; "Opening existing dot repository");
+        let repo = Repository::open(&dot_dir)
+            .with_context(format!("can not open git repository at {:?}", dot_dir))?;
+        match args.git_ref.or_else(|| remembered_ref(&repo)) {
+            Some(git_ref) => {
+                remember_ref(prefix, &repo, &git_ref)?;
+                checkout_ref(prefix, &repo, &git_ref, true)?;
+            }
+            None => pull(prefix, &repo, None, None, true)?,
+        }
     } else {
-        log::info!(repo:? = args.repo, dest:? = dot_dir; "Cloning dot repository");
-        clone(&args.repo, &dot_dir, true);
+        log::info!(repo:? = repo_url, dest:? = dot_dir; "Cloning dot repository");
+        clone(prefix, &repo_url, &dot_dir, true)?;
+        if let Some(git_ref) = &args.git_ref
+            && !prefix.dry_run(format_args!("check out {} in {:?}", git_ref, dot_dir))
+        {
+            let repo = Repository::open(&dot_dir)
+                .with_context(format!("can not open git repository at {:?}", dot_dir))?;
+            remember_ref(prefix, &repo, git_ref)?;
+            checkout_ref(prefix, &repo, git_ref, true)?;
+        }
     }
 
-    match args.shell {
-        Shell::Zsh => zsh::generate_zshenv(
-            prefix.prefix(),
-            &dot_dir,
-            &code_dir,
-            local_dir,
-            &bin_dir,
-            args.rc_file.as_deref(),
-        ),
-    }
+    let layout = DotConfig::load(prefix)?.layout;
+    let (migrated, moved);
+    let prefix = if layout != prefix.layout() {
+        migrated = prefix.to_layout(layout);
+        moved = migrate::migrate(prefix, &migrated)?;
+        relocate(&migrated, &moved)?;
+        for key in outdated_ssh_configs(&migrated)? {
+            key.generate_ssh_config(&migrated)?;
+        }
+        &migrated
+    } else {
+        moved = vec![];
+        prefix
+    };
+    let (dot_dir, bin_dir) = (prefix.dot(), prefix.bin());
+
+    args.shell.generate_rc(prefix, args.rc_file.as_deref())?;
 
-    prefix.create_dir_all();
-    let from_dot = std::env::current_exe().unwrap().canonicalize().unwrap();
+    prefix.create_dir_all()?;
+    if args.shell == Shell::Zsh {
+        install_zsh_completion(prefix)?;
+    }
+    if args.shell == Shell::Zsh && prefix.config_plugin().join("plugins.toml").exists() {
+        plugin::install(prefix, &[])?;
+    }
+    render_all(prefix)?;
+    let secrets = secret_files(prefix)?;
+    if !secrets.is_empty() {
+        let identities = identities(prefix, &[])?;
+        if identities.is_empty() {
+            log::warn!("No age identity available, run `dot secret decrypt` after `dot ssh`");
+        } else {
+            // A secret for the recipients of another host must not abort the whole init.
+            for secret in secrets {
+                if let Err(e) = decrypt_all(prefix, &identities, std::slice::from_ref(&secret)) {
+                    log::warn!(path:? = secret, error:% = e; "Can not decrypt secret, skipping");
+                }
+            }
+        }
+    }
+    if prefix.config_link().join("link.toml").exists() {
+        let report = link(prefix, &[], args.adopt)?;
+        if !report.conflicts().is_empty() {
+            log::warn!(
+                targets:? = report.conflicts();
+                "Skipped existing targets, run `dot link --adopt`"
+            );
+        }
+    }
+    let from_dot =
+        std::env::current_exe().and_then(|path| path.canonicalize()).with_path("current exe")?;
     let to_dot = bin_dir.join("dot");
-    if from_dot != to_dot {
+    if from_dot != to_dot && !prefix.dry_run(format_args!("copy {:?} to {:?}", from_dot, to_dot)) {
         log::info!(from:? = from_dot, to:? = to_dot; "Copying dot binary");
-        std::fs::copy(from_dot, to_dot).unwrap();
+        std::fs::copy(&from_dot, &to_dot).with_path(&to_dot)?;
+    }
+
+    let bootstrap = if args.bootstrap { Some(bootstrap(prefix)?) } else { None };
+
+    prefix.emit(&InitReport {
+        rc_file: args.shell.rc_path(prefix, args.rc_file.as_deref()),
+        repo: repo_url,
+        dot: dot_dir,
+        bin: bin_dir,
+        moved,
+        bootstrap,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_generate_rc_bash() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        Shell::Bash.generate_rc(&prefix, Some(".bashrc.dot")).unwrap();
+
+        let bashrc = std::fs::read_to_string(prefix.prefix().join(".bashrc.dot")).unwrap();
+        assert!(bashrc.contains(&format!("export DOTDIR={}\n", prefix.dot().display())));
+        assert!(bashrc.contains("source \"$BASHDIR/bashrc.bash\"\n"));
+        assert!(!prefix.prefix().join(".bashrc").exists());
+        let bash_profile = std::fs::read_to_string(prefix.prefix().join(".bash_profile")).unwrap();
+        assert!(bash_profile.contains(". \"$HOME/.profile\""));
+        assert!(
+            bash_profile
+                .contains(&format!("source {}", prefix.prefix().join(".bashrc.dot").display()))
+        );
+    }
+
+    #[test]
+    fn test_generate_rc_bash_existing_profile() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let profile = prefix.prefix().join(".profile");
+        std::fs::write(&profile, "export EDITOR=vi\n").unwrap();
+        Shell::Bash.generate_rc(&prefix, None).unwrap();
+
+        // Creating `.bash_profile` would make login shells skip `.profile`.
+        assert!(!prefix.prefix().join(".bash_profile").exists());
+        let content = std::fs::read_to_string(&profile).unwrap();
+        assert!(content.starts_with("export EDITOR=vi\n"));
+        assert!(content.contains(&format!(
+            "[ -n \"$BASH_VERSION\" ] && [ -f {}",
+            prefix.prefix().join(".bashrc").display()
+        )));
+        assert_eq!(Shell::Bash.rc_paths(&prefix), [prefix.prefix().join(".bashrc"), profile]);
+    }
+
+    #[test]
+    fn test_generate_rc_fish() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let sh_dir = prefix.dot().join("shell").join("common");
+        std::fs::create_dir_all(&sh_dir).unwrap();
+        std::fs::write(sh_dir.join("path.sh"), "export PATH=\"$BINDIR:$PATH\"\n").unwrap();
+        Shell::Fish.generate_rc(&prefix, None).unwrap();
+
+        let dot_fish = std::fs::read_to_string(
+            prefix.prefix().join(".config").join("fish").join("conf.d").join("dot.fish"),
+        )
+        .unwrap();
+        assert!(dot_fish.contains(&format!("set -gx BINDIR {}\n", prefix.bin().display())));
+        assert!(dot_fish.contains("# Translated from path.sh\nset -gx PATH \"$BINDIR\" $PATH\n"));
     }
 }
@@ -3,17 +3,21 @@ use std::fs::Permissions;
 use std::io::{Cursor, Read};
 use std::marker::PhantomData;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::ValueEnum;
 use flate2::bufread::GzDecoder;
 use indicatif::{ProgressBar, ProgressStyle};
+use minisign_verify::PublicKey;
 use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
 use tar::Archive;
 use tempfile::TempDir;
 use zip::ZipArchive;
 
 use super::BinaryArgs;
+use crate::error::{Error, Result, WithPath, WithUrl, output};
+use crate::network::NetworkConfig;
 use crate::prefix::Prefix;
 use crate::utils::unwrap_or_missing_argument;
 
@@ -22,6 +26,8 @@ const CHUNK_SIZE: usize = 1024 * 1024;
 
 pub const VERSION_PATTERN: &str = "%VERSION%";
 
+pub const SIGNATURE_SUFFIX: &str = ".minisig";
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum ArchiveType {
     #[clap(name = "tar.gz")]
@@ -31,23 +37,26 @@ pub enum ArchiveType {
 }
 
 impl ArchiveType {
-    fn extract_tar_gz(data: &[u8], dir: impl AsRef<Path>) -> Option<Vec<u8>> {
-        Archive::new(GzDecoder::new(data)).unpack(dir).unwrap();
-        None
+    fn extract_tar_gz(data: &[u8], dir: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
+        Archive::new(GzDecoder::new(data)).unpack(&dir).with_path(dir)?;
+        Ok(None)
     }
 
-    fn extract_gz(data: &[u8], _: impl AsRef<Path>) -> Option<Vec<u8>> {
+    fn extract_gz(data: &[u8], dir: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
         let mut buf = vec![];
-        GzDecoder::new(data).read_to_end(&mut buf).unwrap();
-        Some(buf)
+        GzDecoder::new(data).read_to_end(&mut buf).with_path(dir)?;
+        Ok(Some(buf))
     }
 
-    fn extract_zip(data: &[u8], dir: impl AsRef<Path>) -> Option<Vec<u8>> {
-        ZipArchive::new(Cursor::new(data)).unwrap().extract(dir).unwrap();
-        None
+    fn extract_zip(data: &[u8], dir: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
+        ZipArchive::new(Cursor::new(data))
+            .and_then(|mut archive| archive.extract(&dir))
+            .map_err(std::io::Error::from)
+            .with_path(dir)?;
+        Ok(None)
     }
 
-    fn extract(self, data: &[u8], dir: impl AsRef<Path>) -> Option<Vec<u8>> {
+    fn extract(self, data: &[u8], dir: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
         match self {
             ArchiveType::TarGz => Self::extract_tar_gz(data, dir),
             ArchiveType::Gz => Self::extract_gz(data, dir),
@@ -56,6 +65,73 @@ impl ArchiveType {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct Signature<'a> {
+    /// Base64 encoded minisign public key.
+    pub public_key: &'a str,
+    /// Suffix appended to the binary url to get the signature url.
+    pub suffix: &'a str,
+    /// First signed version, older releases are downloaded without verification.
+    pub since: Option<&'a str>,
+}
+
+impl Signature<'_> {
+    /// Whether `version` is expected to be signed. A version which can not be compared is.
+    fn is_required(&self, version: &str) -> bool {
+        let parse = |version: &str| semver::Version::parse(version.trim_start_matches('v'));
+        match (self.since.map(parse), parse(version)) {
+            (Some(Ok(since)), Ok(version)) => version >= since,
+            _ => true,
+        }
+    }
+
+    fn verify(&self, data: &[u8], signature: &str, url: &str) -> Result<()> {
+        PublicKey::from_base64(self.public_key)
+            .and_then(|public_key| {
+                public_key.verify(data, &minisign_verify::Signature::decode(signature)?, false)
+            })
+            .with_url(url)
+    }
+}
+
+/// Command run with the installed binary whose output is cached for the shell.
+#[derive(Debug, Copy, Clone)]
+pub enum Hook<'a> {
+    /// Init script sourced at shell startup.
+    Init(&'a [&'a str]),
+    /// Completion function placed on `$fpath`.
+    Completion(&'a [&'a str]),
+}
+
+impl Hook<'_> {
+    fn run(&self, prefix: &Prefix, name: &str, bin_path: impl AsRef<Path>) -> Result<()> {
+        let (args, path) = match self {
+            Hook::Init(args) => (args, prefix.hook().join(format!("{}.zsh", name))),
+            Hook::Completion(args) => (args, prefix.completion().join(format!("_{}", name))),
+        };
+        let mut command = std::process::Command::new(bin_path.as_ref());
+        command.args(*args);
+        log::info!(command:? = command, path:? = path; "Running post-install hook");
+        if prefix.dry_run(format_args!("write output of {:?} to {:?}", command, path)) {
+            return Ok(());
+        }
+
+        let stdout = output(&mut command)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_path(dir)?;
+        }
+        std::fs::write(&path, stdout).with_path(&path)
+    }
+}
+
+/// Binary placed into `Prefix::bin()`.
+#[derive(Debug, Serialize)]
+pub struct InstalledBinary {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+}
+
 pub struct Binary<'a, 'b, 'c, 'd, 't, T: Debug + 't>
 where
     &'t T: IntoIterator<Item = &'t &'c str>,
@@ -65,6 +141,8 @@ where
     pub url: &'b str,
     pub archive: Option<(ArchiveType, Option<T>)>,
     pub version_arg: &'d str,
+    pub signature: Option<Signature<'b>>,
+    pub hooks: &'d [Hook<'d>],
     pub phantom_c: PhantomData<&'c str>,
     pub phantom_t: PhantomData<&'t T>,
 }
@@ -75,10 +153,13 @@ where
     'c: 't,
     's: 't,
 {
-    pub fn download(&'s self, prefix: &Prefix, bin_version: &str) {
+    /// Download, verify and extract the binary into a temporary executable inside `Prefix::bin()`
+    /// without touching the installed one.
+    pub fn stage(&'s self, prefix: &Prefix, bin_version: &str) -> Result<PathBuf> {
         let bin_dir = prefix.bin();
-        let bin_path = bin_dir.join(self.name);
-        let url = self.url.replace(VERSION_PATTERN, bin_version);
+        let bin_tmp_path = bin_dir.join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
+        let network = NetworkConfig::load(prefix)?;
+        let url = network.rewrite(&self.url.replace(VERSION_PATTERN, bin_version));
         log::info!(name = self.name, url:% = url; "Downloading binary");
 
         let pb = ProgressBar::new_spinner().with_style(
@@ -90,11 +171,18 @@ where
 
         let mut buf = vec![];
         let mut buf_len = 0;
-        let mut reader = reqwest::blocking::get(&url).unwrap();
+        let client = network.client()?;
+        let mut reader = client
+            .get(&url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .with_url(&url)?;
         loop {
             buf.extend_from_slice(&[0; CHUNK_SIZE]);
             let chunk = &mut buf.as_mut_slice()[buf_len..buf_len + CHUNK_SIZE];
-            let read_len = reader.read(chunk).unwrap();
+            let read_len = reader
+                .read(chunk)
+                .map_err(|e| Error::Config(format!("can not read response from {}: {}", url, e)))?;
 
             if read_len == 0 {
                 break;
@@ -108,10 +196,28 @@ where
         pb.finish_and_clear();
         log::info!(name = self.name, elapsed:? = pb.elapsed(); "Finish downloading");
 
+        match self.signature.as_ref() {
+            Some(signature) if signature.is_required(bin_version) => {
+                let signature_url = format!("{}{}", url, signature.suffix);
+                log::info!(name = self.name, url:% = signature_url; "Verifying signature");
+                let signature_text = client
+                    .get(&signature_url)
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.text())
+                    .with_url(&signature_url)?;
+                signature.verify(&buf, &signature_text, &signature_url)?;
+            }
+            Some(_) => {
+                log::warn!(name = self.name, version = bin_version; "Release predates signing")
+            }
+            None => {}
+        }
+
         let buf = if let Some((archive_type, archive_paths)) = self.archive.as_ref() {
             log::info!(name = self.name, archive:? = self.archive; "Extracting binary");
-            let temp_dir = TempDir::new().unwrap();
-            if let Some(buf) = archive_type.extract(&buf, &temp_dir) {
+            let temp_dir = TempDir::new().with_path(std::env::temp_dir())?;
+            if let Some(buf) = archive_type.extract(&buf, &temp_dir)? {
                 Some(buf)
             } else {
                 let mut archive_path = temp_dir.path().to_path_buf();
@@ -123,10 +229,7 @@ where
                 } else {
                     archive_path = archive_path.join(self.name);
                 }
-                let bin_tmp_path =
-                    bin_dir.join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
-                std::fs::copy(archive_path, &bin_tmp_path).unwrap();
-                std::fs::rename(&bin_tmp_path, &bin_path).unwrap();
+                std::fs::copy(&archive_path, &bin_tmp_path).with_path(&archive_path)?;
                 None
             }
         } else {
@@ -134,22 +237,43 @@ where
         };
 
         if let Some(buf) = buf {
-            let bin_tmp_path =
-                bin_dir.join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
-            std::fs::write(&bin_tmp_path, buf).unwrap();
-            std::fs::rename(&bin_tmp_path, &bin_path).unwrap();
+            std::fs::write(&bin_tmp_path, buf).with_path(&bin_tmp_path)?;
         }
-        std::fs::set_permissions(&bin_path, Permissions::from_mode(0o777)).unwrap();
+        std::fs::set_permissions(&bin_tmp_path, Permissions::from_mode(0o777))
+            .with_path(&bin_tmp_path)?;
+        Ok(bin_tmp_path)
+    }
+
+    pub fn download(&'s self, prefix: &Prefix, bin_version: &str) -> Result<InstalledBinary> {
+        let bin_path = prefix.bin().join(self.name);
+        let installed = InstalledBinary {
+            name: self.name.to_owned(),
+            version: bin_version.to_owned(),
+            path: bin_path.clone(),
+        };
+        if prefix.is_dry_run() {
+            let url = NetworkConfig::load(prefix)?
+                .rewrite(&self.url.replace(VERSION_PATTERN, bin_version));
+            prefix.dry_run(format_args!("download {} to {:?}", url, bin_path));
+            for hook in self.hooks {
+                hook.run(prefix, self.name, &bin_path)?;
+            }
+            return Ok(installed);
+        }
+        let bin_tmp_path = self.stage(prefix, bin_version)?;
 
         log::info!(name = self.name, arg = self.version_arg; "Downloaded binary version");
-        std::process::Command::new(&bin_path)
-            .arg(self.version_arg)
-            .spawn()
-            .unwrap()
-            .wait()
-            .unwrap()
-            .exit_ok()
-            .unwrap();
+        if let Err(e) = prefix.run(std::process::Command::new(&bin_tmp_path).arg(self.version_arg))
+        {
+            std::fs::remove_file(&bin_tmp_path).with_path(&bin_tmp_path)?;
+            return Err(e);
+        }
+        std::fs::rename(&bin_tmp_path, &bin_path).with_path(&bin_path)?;
+
+        for hook in self.hooks {
+            hook.run(prefix, self.name, &bin_path)?;
+        }
+        Ok(installed)
     }
 }
 
@@ -171,8 +295,83 @@ where
                 (t, value.archive_paths.as_ref().map(|v| v.iter().map(String::as_str).collect()))
             }),
             version_arg: version_arg.trim_matches('^'),
+            signature: value.public_key.as_deref().map(|public_key| Signature {
+                public_key,
+                suffix: value.signature_suffix.as_deref().unwrap_or(SIGNATURE_SUFFIX),
+                since: None,
+            }),
+            hooks: &[],
             phantom_c: std::marker::PhantomData,
             phantom_t: std::marker::PhantomData,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/\
+                             z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    #[test]
+    fn test_verify_signature() {
+        Signature { public_key: PUBLIC_KEY, suffix: SIGNATURE_SUFFIX, since: None }
+            .verify(b"test", SIGNATURE, "test.minisig")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_signature_since() {
+        use super::super::config::FIRST_SIGNED_DOT_VERSION;
+
+        let signature = Signature {
+            public_key: PUBLIC_KEY,
+            suffix: SIGNATURE_SUFFIX,
+            since: Some(FIRST_SIGNED_DOT_VERSION),
+        };
+        assert!(!signature.is_required("0.8.3"));
+        assert!(signature.is_required(FIRST_SIGNED_DOT_VERSION));
+        // Releases older than the running build are still verified.
+        assert!(signature.is_required(env!("CARGO_PKG_VERSION")));
+        assert!(signature.is_required("0.8.9"));
+        assert!(signature.is_required("v0.9.0"));
+        assert!(signature.is_required("latest"));
+    }
+
+    #[test]
+    fn test_verify_signature_tampered() {
+        assert!(matches!(
+            Signature { public_key: PUBLIC_KEY, suffix: SIGNATURE_SUFFIX, since: None }.verify(
+                b"tset",
+                SIGNATURE,
+                "test.minisig"
+            ),
+            Err(Error::Signature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_hook() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let bin_path = prefix.bin().join("tool");
+        std::fs::write(&bin_path, "#!/bin/sh\necho \"$@\"\n").unwrap();
+        std::fs::set_permissions(&bin_path, Permissions::from_mode(0o777)).unwrap();
+
+        Hook::Init(&["init", "zsh"]).run(&prefix, "tool", &bin_path).unwrap();
+        Hook::Completion(&["--completions", "zsh"]).run(&prefix, "tool", &bin_path).unwrap();
+        assert_eq!(std::fs::read_to_string(prefix.hook().join("tool.zsh")).unwrap(), "init zsh\n");
+        assert_eq!(
+            std::fs::read_to_string(prefix.completion().join("_tool")).unwrap(),
+            "--completions zsh\n"
+        );
+    }
+}
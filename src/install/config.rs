@@ -5,23 +5,33 @@ use std::sync::OnceLock;
 
 use const_format::{Case, formatc, map_ascii_case, str_replace};
 
-use super::binary::{ArchiveType, Binary, VERSION_PATTERN};
+use super::binary::{
+    ArchiveType, Binary, Hook, InstalledBinary, SIGNATURE_SUFFIX, Signature, VERSION_PATTERN,
+};
 use crate::constant::target::*;
+use crate::error::{Error, Result};
 use crate::prefix::Prefix;
+use crate::utils::load_toml;
 
 impl InstallConfig {
-    pub fn load_predefined_version(prefix: &Prefix) -> &'static HashMap<Self, String> {
+    /// Read the pinned versions of `version.toml`, bypassing the cache of
+    /// `load_predefined_version`.
+    pub fn load_version(prefix: &Prefix) -> Result<HashMap<Self, String>> {
+        let configs: HashMap<String, String> =
+            load_toml(prefix.config_binary().join("version.toml"))?;
+        configs
+            .into_iter()
+            .map(|(k, v)| {
+                InstallConfig::from_str(&k, true)
+                    .map(|config| (config, v))
+                    .map_err(|e| Error::Config(format!("invalid binary {}: {}", k, e)))
+            })
+            .collect()
+    }
+
+    pub fn load_predefined_version(prefix: &Prefix) -> Result<&'static HashMap<Self, String>> {
         static PREDEFINED_CONFIG: OnceLock<HashMap<InstallConfig, String>> = OnceLock::new();
-        PREDEFINED_CONFIG.get_or_init(|| {
-            let configs: HashMap<String, String> = toml::from_str(
-                &std::fs::read_to_string(prefix.config_binary().join("version.toml")).unwrap(),
-            )
-            .unwrap();
-            configs
-                .into_iter()
-                .map(|(k, v)| (InstallConfig::from_str(&k, true).unwrap(), v))
-                .collect()
-        })
+        PREDEFINED_CONFIG.get_or_try_init(|| Self::load_version(prefix))
     }
 }
 
@@ -34,6 +44,11 @@ pub const STARSHIP_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["starship"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[
+        Hook::Init(&["init", "zsh", "--print-full-init"]),
+        Hook::Completion(&["completions", "zsh"]),
+    ],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -48,6 +63,8 @@ pub const DIRENV_BINARY: Binary<[&str; 0]> = Binary {
     ),
     archive: None,
     version_arg: "--version",
+    signature: None,
+    hooks: &[Hook::Init(&["hook", "zsh"])],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -62,6 +79,8 @@ pub const RYE_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::Gz, Some(["bin"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -75,6 +94,8 @@ pub const EZA_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["eza"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -90,6 +111,8 @@ pub const CROC_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["croc"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -104,6 +127,8 @@ pub const JUST_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["just"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[Hook::Completion(&["--completions", "zsh"])],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -119,10 +144,16 @@ pub const SKM_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["skm"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
 
+/// First release of `dot` signed by the release workflow. Older releases are downloaded
+/// without verification, every later one must carry a valid signature.
+pub const FIRST_SIGNED_DOT_VERSION: &str = "0.8.8";
+
 pub const DOT_BINARY: Binary<[&str; 0]> = Binary {
     name: "dot",
     url: formatc!(
@@ -132,6 +163,17 @@ pub const DOT_BINARY: Binary<[&str; 0]> = Binary {
     ),
     archive: None,
     version_arg: "--version",
+    // Release builds embed the key which signs the released `dot` binaries. The variable is
+    // empty rather than unset when the CI has no key, such as in forks.
+    signature: match option_env!("DOT_MINISIGN_PUBLIC_KEY") {
+        Some(public_key) if !public_key.is_empty() => Some(Signature {
+            public_key,
+            suffix: SIGNATURE_SUFFIX,
+            since: Some(FIRST_SIGNED_DOT_VERSION),
+        }),
+        _ => None,
+    },
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -146,6 +188,8 @@ pub const ZOXIDE_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["zoxide"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[Hook::Init(&["init", "zsh"])],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -159,6 +203,8 @@ pub const ZELLIJ_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::TarGz, Some(["zellij"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -176,6 +222,8 @@ pub const BAT_BINARY: Binary<[&str; 2]> = Binary {
         Some([formatc!("bat-v{}-{}", VERSION_PATTERN, TARGET_TRIPLET), "bat"]),
     )),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -196,6 +244,8 @@ pub const RIPGREP_BINARY: Binary<[&str; 2]> = Binary {
         ]),
     )),
     version_arg: "--version",
+    signature: None,
+    hooks: &[Hook::Completion(&["--generate", "complete-zsh"])],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
@@ -209,14 +259,77 @@ pub const RATHOLE_BINARY: Binary<[&str; 1]> = Binary {
     ),
     archive: Some((ArchiveType::Zip, Some(["rathole"]))),
     version_arg: "--version",
+    signature: None,
+    hooks: &[],
     phantom_c: std::marker::PhantomData,
     phantom_t: std::marker::PhantomData,
 };
 
 impl InstallConfig {
-    pub fn download(self, prefix: &Prefix, bin_version: Option<&str>) {
-        let bin_version = bin_version
-            .unwrap_or_else(|| InstallConfig::load_predefined_version(prefix).get(&self).unwrap());
+    pub fn name(self) -> &'static str {
+        match self {
+            InstallConfig::Starship => STARSHIP_BINARY.name,
+            InstallConfig::Direnv => DIRENV_BINARY.name,
+            InstallConfig::Rye => RYE_BINARY.name,
+            InstallConfig::Eza => EZA_BINARY.name,
+            InstallConfig::Croc => CROC_BINARY.name,
+            InstallConfig::Just => JUST_BINARY.name,
+            InstallConfig::Skm => SKM_BINARY.name,
+            InstallConfig::Dot => DOT_BINARY.name,
+            InstallConfig::Zoxide => ZOXIDE_BINARY.name,
+            InstallConfig::Zellij => ZELLIJ_BINARY.name,
+            InstallConfig::Bat => BAT_BINARY.name,
+            InstallConfig::Ripgrep => RIPGREP_BINARY.name,
+            InstallConfig::Rathole => RATHOLE_BINARY.name,
+        }
+    }
+
+    pub fn version_arg(self) -> &'static str {
+        match self {
+            InstallConfig::Starship => STARSHIP_BINARY.version_arg,
+            InstallConfig::Direnv => DIRENV_BINARY.version_arg,
+            InstallConfig::Rye => RYE_BINARY.version_arg,
+            InstallConfig::Eza => EZA_BINARY.version_arg,
+            InstallConfig::Croc => CROC_BINARY.version_arg,
+            InstallConfig::Just => JUST_BINARY.version_arg,
+            InstallConfig::Skm => SKM_BINARY.version_arg,
+            InstallConfig::Dot => DOT_BINARY.version_arg,
+            InstallConfig::Zoxide => ZOXIDE_BINARY.version_arg,
+            InstallConfig::Zellij => ZELLIJ_BINARY.version_arg,
+            InstallConfig::Bat => BAT_BINARY.version_arg,
+            InstallConfig::Ripgrep => RIPGREP_BINARY.version_arg,
+            InstallConfig::Rathole => RATHOLE_BINARY.version_arg,
+        }
+    }
+
+    /// Output of the installed binary run with `version_arg`, `None` if it does not run.
+    pub fn installed_version(self, prefix: &Prefix) -> Option<String> {
+        let bin_path = prefix.bin().join(self.name());
+        match std::process::Command::new(&bin_path).arg(self.version_arg()).output() {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the output of `--version` names exactly `version`, a leading `v` is ignored.
+    pub fn reports_version(stdout: &str, version: &str) -> bool {
+        let version = version.trim_start_matches('v');
+        stdout
+            .split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ','))
+            .any(|token| token.trim_start_matches('v') == version)
+    }
+
+    pub fn download(self, prefix: &Prefix, bin_version: Option<&str>) -> Result<InstalledBinary> {
+        let bin_version = match bin_version {
+            Some(bin_version) => bin_version,
+            None => {
+                InstallConfig::load_predefined_version(prefix)?.get(&self).ok_or_else(|| {
+                    Error::Config(format!("version of {} is not pinned", self.name()))
+                })?
+            }
+        };
         match self {
             InstallConfig::Starship => STARSHIP_BINARY.download(prefix, bin_version),
             InstallConfig::Direnv => DIRENV_BINARY.download(prefix, bin_version),
@@ -243,7 +356,7 @@ mod tests {
     use crate::utils::get_dot_dir;
 
     fn copy_version(prefix: &Prefix) {
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         std::fs::copy(
             get_dot_dir().join("config").join("binary").join("version.toml"),
             prefix.config_binary().join("version.toml"),
@@ -251,12 +364,21 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn test_reports_version() {
+        assert!(InstallConfig::reports_version("starship 1.22.1\nbranch:\n", "1.22.1"));
+        assert!(InstallConfig::reports_version("eza\nv0.20.16 [+git]\n", "0.20.16"));
+        assert!(InstallConfig::reports_version("rg 14.1.1 (rev 4649aa9700)\n", "14.1.1"));
+        assert!(!InstallConfig::reports_version("tool 0.1.0-rc\n", "0.1.0"));
+        assert!(!InstallConfig::reports_version("tool 11.2.3\n", "1.2"));
+    }
+
     #[test]
     fn test_install_starship() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Starship.download(&prefix, None);
+        InstallConfig::Starship.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -264,7 +386,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Direnv.download(&prefix, None);
+        InstallConfig::Direnv.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -272,7 +394,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Rye.download(&prefix, None);
+        InstallConfig::Rye.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -281,7 +403,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Eza.download(&prefix, None);
+        InstallConfig::Eza.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -289,7 +411,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Croc.download(&prefix, None);
+        InstallConfig::Croc.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -297,7 +419,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Just.download(&prefix, None);
+        InstallConfig::Just.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -305,7 +427,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Skm.download(&prefix, None);
+        InstallConfig::Skm.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -314,7 +436,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Dot.download(&prefix, None);
+        InstallConfig::Dot.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -322,7 +444,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Zoxide.download(&prefix, None);
+        InstallConfig::Zoxide.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -330,7 +452,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Zellij.download(&prefix, None);
+        InstallConfig::Zellij.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -338,7 +460,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Bat.download(&prefix, None);
+        InstallConfig::Bat.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -346,7 +468,7 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Ripgrep.download(&prefix, None);
+        InstallConfig::Ripgrep.download(&prefix, None).unwrap();
     }
 
     #[test]
@@ -355,6 +477,6 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
         copy_version(&prefix);
-        InstallConfig::Rathole.download(&prefix, None);
+        InstallConfig::Rathole.download(&prefix, None).unwrap();
     }
 }
@@ -2,9 +2,12 @@ mod binary;
 mod config;
 
 use clap::Args;
+use serde::Serialize;
 
-use self::binary::{ArchiveType, Binary};
-use self::config::InstallConfig;
+use self::binary::{ArchiveType, Binary, InstalledBinary};
+pub use self::config::{DOT_BINARY, InstallConfig};
+use crate::error::Result;
+use crate::output::Report;
 use crate::prefix::Prefix;
 use crate::utils::unwrap_or_missing_argument;
 
@@ -38,22 +41,30 @@ pub struct BinaryArgs {
     /// A `^` can be addded to the beginning to avoid parsing error.
     #[arg(long)]
     pub version_arg: Option<String>,
+    /// Minisign public key to verify the downloaded binary.
+    #[arg(long)]
+    pub public_key: Option<String>,
+    /// Suffix appended to the url to download the signature. Default to `.minisig`.
+    #[arg(long)]
+    pub signature_suffix: Option<String>,
 }
 
-pub fn entry_install(prefix: &Prefix, args: InstallArgs) {
-    if !args.configs.is_empty() {
-        for config in args.configs {
-            config.download(prefix, args.bin_version.as_deref());
-        }
+#[derive(Debug, Serialize)]
+struct InstallReport {
+    binaries: Vec<InstalledBinary>,
+}
+
+impl Report for InstallReport {}
+
+pub fn entry_install(prefix: &Prefix, args: InstallArgs) -> Result<()> {
+    let binaries = if !args.configs.is_empty() {
+        args.configs
+            .into_iter()
+            .map(|config| config.download(prefix, args.bin_version.as_deref()))
+            .collect::<Result<_>>()?
     } else {
-        let bin_version = match unwrap_or_missing_argument(args.bin_version, "--bin-version", None)
-        {
-            Ok(bin_version) => bin_version,
-            Err(e) => e.exit(),
-        };
-        match Binary::try_from(&args.binary) {
-            Ok(binary) => binary.download(prefix, &bin_version),
-            Err(e) => e.exit(),
-        }
-    }
+        let bin_version = unwrap_or_missing_argument(args.bin_version, "--bin-version", None)?;
+        vec![Binary::try_from(&args.binary)?.download(prefix, &bin_version)?]
+    };
+    prefix.emit(&InstallReport { binaries })
 }
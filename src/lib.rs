@@ -1,28 +1,73 @@
 #![deny(clippy::all)]
-#![feature(exit_status_error)]
 #![feature(let_chains)]
+#![feature(once_cell_try)]
 
+mod bootstrap;
+mod completion;
+mod config;
 mod constant;
+mod deinit;
+mod doctor;
+mod error;
 mod git;
 mod init;
 mod install;
+mod link;
+mod man;
+mod network;
+mod output;
+mod plugin;
 mod prefix;
+mod relocate;
+mod secret;
+mod self_update;
+mod template;
+mod update;
 mod utils;
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 mod ssh;
+mod status;
 use clap::{Args, Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use completion::CompletionsArgs;
+use deinit::DeinitArgs;
+use doctor::DoctorArgs;
 use git::GitArgs;
 use init::InitArgs;
 use install::InstallArgs;
+use link::{LinkArgs, UnlinkArgs};
+use man::ManArgs;
+use output::Output;
+use plugin::PluginArgs;
 use prefix::Prefix;
+use relocate::PrefixArgs;
+use secret::SecretArgs;
+use self_update::SelfUpdateArgs;
 use ssh::SshArgs;
+use status::StatusArgs;
+use template::DiffArgs;
+use update::UpdateArgs;
 
+use crate::completion::entry_completions;
+use crate::deinit::entry_deinit;
+use crate::doctor::entry_doctor;
+use crate::error::{Error, Result};
 use crate::git::entry_git;
 use crate::init::entry_init;
 use crate::install::entry_install;
+use crate::link::{entry_link, entry_unlink};
+use crate::man::entry_man;
+use crate::network::NetworkConfig;
+use crate::plugin::entry_plugin;
+use crate::relocate::entry_prefix;
+use crate::secret::entry_secret;
+use crate::self_update::entry_self_update;
 use crate::ssh::entry_ssh;
+use crate::status::entry_status;
+use crate::template::entry_diff;
+use crate::update::entry_update;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -38,12 +83,38 @@ pub struct Cli {
 pub enum Command {
     /// Initialize dot environment.
     Init(InitArgs),
+    /// Remove what `dot init` generated.
+    Deinit(DeinitArgs),
     /// Install binary from internet.
     Install(InstallArgs),
+    /// Link files from the dot repository into the home directory.
+    Link(LinkArgs),
+    /// Remove the links created by `dot link`.
+    Unlink(UnlinkArgs),
+    /// Show what rendering the templates of the dot repository would change.
+    Diff(DiffArgs),
+    /// Encrypt and decrypt the secrets of the dot repository.
+    Secret(SecretArgs),
+    /// Manage the zsh plugins of `plugins.toml`.
+    Plugin(PluginArgs),
+    /// Move the dot environment to another prefix.
+    Prefix(PrefixArgs),
     /// Generate ssh config with host.
     Ssh(SshArgs),
     /// Utility to work with git repository.
     Git(GitArgs),
+    /// Show the state of the dot repository and what drifted from it.
+    Status(StatusArgs),
+    /// Pull the dot repository and refresh what changed since the last update.
+    Update(UpdateArgs),
+    /// Update the dot binary itself.
+    SelfUpdate(SelfUpdateArgs),
+    /// Check the health of the dot environment.
+    Doctor(DoctorArgs),
+    /// Generate shell completions.
+    Completions(CompletionsArgs),
+    /// Generate man pages.
+    Man(ManArgs),
 }
 
 #[derive(Debug, Args)]
@@ -53,25 +124,64 @@ pub struct Global {
     /// Prefix of the dot environment.
     /// Will be resolved in this order:
     /// 1. The argument supplied to this option.
-    /// 2. The parent folder of $DOTDIR.
+    /// 2. The parent folder of $DOTDIR in the legacy layout.
     /// 3. The home directory of current user.
     #[arg(short, long, global = true)]
     pub prefix: Option<PathBuf>,
+    /// Report the file writes, git changes and downloads without performing them.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Format of the result printed on stdout. Logs are always written to stderr.
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
 }
 
-pub fn entry(cli: Cli) {
+fn run(cli: Cli) -> Result<()> {
+    let prefix = Prefix::new(cli.global.prefix)?
+        .with_dry_run(cli.global.dry_run)
+        .with_output(cli.global.output);
+    NetworkConfig::load(&prefix)?.setup_git(&prefix)?;
+    match cli.command {
+        Command::Init(args) => entry_init(&prefix, args),
+        Command::Deinit(args) => entry_deinit(&prefix, args),
+        Command::Install(args) => entry_install(&prefix, args),
+        Command::Link(args) => entry_link(&prefix, args),
+        Command::Unlink(args) => entry_unlink(&prefix, args),
+        Command::Diff(args) => entry_diff(&prefix, args),
+        Command::Secret(args) => entry_secret(&prefix, args),
+        Command::Plugin(args) => entry_plugin(&prefix, args),
+        Command::Prefix(args) => entry_prefix(&prefix, args),
+        Command::Ssh(args) => entry_ssh(&prefix, args),
+        Command::Git(args) => entry_git(&prefix, args),
+        Command::Status(args) => entry_status(&prefix, args),
+        Command::Update(args) => entry_update(&prefix, args),
+        Command::SelfUpdate(args) => entry_self_update(&prefix, args),
+        Command::Doctor(args) => entry_doctor(&prefix, args),
+        Command::Completions(args) => entry_completions(&prefix, args),
+        Command::Man(args) => entry_man(&prefix, args),
+    }
+}
+
+pub fn entry(cli: Cli) -> ExitCode {
     env_logger::builder()
         .filter_module("dot", cli.global.verbose.log_level_filter())
         .format_module_path(false)
         .format_target(false)
+        .target(env_logger::Target::Stderr)
         .init();
     log::trace!("\n{:#?}", cli);
 
-    let prefix = Prefix::new(cli.global.prefix);
-    match cli.command {
-        Command::Init(args) => entry_init(&prefix, args),
-        Command::Install(args) => entry_install(&prefix, args),
-        Command::Ssh(args) => entry_ssh(&prefix, args),
-        Command::Git(args) => entry_git(&prefix, args),
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Cli(e)) => e.exit(),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                eprintln!("  caused by: {}", cause);
+                source = cause.source();
+            }
+            ExitCode::from(e.exit_code())
+        }
     }
 }
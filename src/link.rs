@@ -0,0 +1,455 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result, WithPath};
+use crate::output::Report;
+use crate::prefix::{Prefix, XdgDir};
+use crate::utils::{load_predefined_and_local, load_toml, rebase};
+
+#[derive(Debug, Args)]
+pub struct LinkArgs {
+    /// Names from the link map, all of them if none is supplied.
+    pub names: Vec<String>,
+    /// Move conflicting targets into the dot repository before linking them.
+    #[arg(long)]
+    pub adopt: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct UnlinkArgs {
+    /// Names of the created links, all of them if none is supplied.
+    pub names: Vec<String>,
+    /// Only remove links which are no longer in the link map or whose source is gone.
+    #[arg(long, conflicts_with = "names")]
+    pub stale: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    #[default]
+    Symlink,
+    Copy,
+}
+
+/// An entry of `config/link/link.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkConfig {
+    /// Path relative to the dot repository.
+    source: PathBuf,
+    /// Path starting with `~` or one of the `$XDG_*` directories.
+    target: String,
+    #[serde(default)]
+    mode: LinkMode,
+}
+
+/// A link created by `dot link`, remembered so that `dot unlink` only touches its own files.
+#[derive(Debug, Deserialize, Serialize)]
+struct Linked {
+    source: PathBuf,
+    target: PathBuf,
+    mode: LinkMode,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LinkReport {
+    linked: Vec<String>,
+    adopted: Vec<String>,
+    unchanged: Vec<String>,
+    conflicts: Vec<PathBuf>,
+}
+
+impl Report for LinkReport {}
+
+impl LinkReport {
+    pub fn conflicts(&self) -> &[PathBuf] {
+        &self.conflicts
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct UnlinkReport {
+    removed: Vec<String>,
+    /// Targets modified since they were created, left in place.
+    kept: Vec<PathBuf>,
+}
+
+impl Report for UnlinkReport {}
+
+fn link_map_path(prefix: &Prefix) -> PathBuf {
+    prefix.config_link().join("link.toml")
+}
+
+pub fn state_path(prefix: &Prefix) -> PathBuf {
+    prefix.state().join("link.toml")
+}
+
+fn load_state(prefix: &Prefix) -> Result<BTreeMap<String, Linked>> {
+    let path = state_path(prefix);
+    if path.exists() { load_toml(path) } else { Ok(BTreeMap::new()) }
+}
+
+fn save_state(prefix: &Prefix, state: &BTreeMap<String, Linked>) -> Result<()> {
+    if prefix.is_dry_run() {
+        return Ok(());
+    }
+    let path = state_path(prefix);
+    let content = toml::to_string(state)
+        .map_err(|e| Error::Config(format!("can not serialize link state: {}", e)))?;
+    std::fs::create_dir_all(prefix.state()).with_path(prefix.state())?;
+    std::fs::write(&path, content).with_path(&path)
+}
+
+fn expand_target(prefix: &Prefix, target: &str) -> Result<PathBuf> {
+    if target == "~" {
+        return Ok(prefix.prefix().into());
+    } else if let Some(rest) = target.strip_prefix("~/") {
+        return Ok(prefix.prefix().join(rest));
+    }
+    for dir in XdgDir::ALL {
+        if let Some(rest) =
+            target.strip_prefix('$').and_then(|target| target.strip_prefix(dir.var()))
+        {
+            return Ok(prefix.xdg(dir).join(rest.trim_start_matches('/')));
+        }
+    }
+    Err(Error::Config(format!("link target {} must start with `~` or `$XDG_*`", target)))
+}
+
+fn copy_all(source: &Path, target: &Path) -> std::io::Result<()> {
+    if source.is_dir() {
+        std::fs::create_dir_all(target)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &target.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(source, target).map(|_| ())
+    }
+}
+
+fn same_content(source: &Path, target: &Path) -> bool {
+    if source.is_dir() && target.is_dir() {
+        let (Ok(sources), Ok(targets)) = (std::fs::read_dir(source), std::fs::read_dir(target))
+        else {
+            return false;
+        };
+        let sources: Vec<_> = sources.filter_map(|entry| entry.ok()).collect();
+        sources.len() == targets.count()
+            && sources
+                .iter()
+                .all(|entry| same_content(&entry.path(), &target.join(entry.file_name())))
+    } else if source.is_file() && target.is_file() {
+        matches!((std::fs::read(source), std::fs::read(target)), (Ok(a), Ok(b)) if a == b)
+    } else {
+        false
+    }
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path).with_path(path)?;
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(path).with_path(path)
+    } else {
+        std::fs::remove_file(path).with_path(path)
+    }
+}
+
+impl Linked {
+    fn is_up_to_date(&self) -> bool {
+        match self.mode {
+            LinkMode::Symlink => std::fs::read_link(&self.target).is_ok_and(|p| p == self.source),
+            LinkMode::Copy => same_content(&self.source, &self.target),
+        }
+    }
+
+    fn create(&self, prefix: &Prefix) -> Result<()> {
+        let (source, target) = (&self.source, &self.target);
+        let action = match self.mode {
+            LinkMode::Symlink => "symlink",
+            LinkMode::Copy => "copy",
+        };
+        if prefix.dry_run(format_args!("{} {:?} to {:?}", action, source, target)) {
+            return Ok(());
+        }
+        log::info!(source:? = source, target:? = target, mode:? = self.mode; "Linking");
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).with_path(parent)?;
+        }
+        match self.mode {
+            LinkMode::Symlink => std::os::unix::fs::symlink(source, target).with_path(target),
+            LinkMode::Copy => copy_all(source, target).with_path(target),
+        }
+    }
+
+    /// Replace the source with the existing target.
+    fn adopt(&self, prefix: &Prefix) -> Result<()> {
+        let (source, target) = (&self.source, &self.target);
+        if prefix.dry_run(format_args!("adopt {:?} into {:?}", target, source)) {
+            return Ok(());
+        }
+        log::info!(source:? = source, target:? = target; "Adopting existing file");
+        if std::fs::symlink_metadata(source).is_ok() {
+            remove_path(source)?;
+        }
+        if std::fs::rename(target, source).is_err() {
+            // The target may live on another file system.
+            copy_all(target, source).with_path(target)?;
+            remove_path(target)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, prefix: &Prefix) -> Result<()> {
+        if prefix.dry_run(format_args!("remove {:?}", self.target)) {
+            return Ok(());
+        }
+        log::info!(target:? = self.target; "Removing link");
+        remove_path(&self.target)
+    }
+}
+
+/// Targets of the links created by `dot link`.
+pub fn created_links(prefix: &Prefix) -> Result<Vec<PathBuf>> {
+    Ok(load_state(prefix)?.into_values().map(|linked| linked.target).collect())
+}
+
+/// Follow the moves of `moves`: the links created by `dot link` are recreated at their moved
+/// targets, pointing at the moved sources, and the remembered links are updated.
+/// A moved target which already exists is left to `dot link`.
+pub fn relocate(prefix: &Prefix, moves: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let mut state = load_state(prefix)?;
+    for linked in state.values_mut() {
+        let source = rebase(&linked.source, moves).unwrap_or_else(|| linked.source.clone());
+        let mut target = rebase(&linked.target, moves).unwrap_or_else(|| linked.target.clone());
+        if target != linked.target && std::fs::symlink_metadata(&target).is_ok() {
+            log::warn!(target:? = target; "Target already exists, keeping the previous one");
+            target = linked.target.clone();
+        }
+        let (is_ours, changed) = match linked.mode {
+            LinkMode::Symlink => (
+                std::fs::read_link(&linked.target).is_ok_and(|current| current == linked.source),
+                source != linked.source || target != linked.target,
+            ),
+            LinkMode::Copy => {
+                (std::fs::symlink_metadata(&linked.target).is_ok(), target != linked.target)
+            }
+        };
+        if is_ours && changed && !prefix.dry_run(format_args!("link {:?} to {:?}", source, target))
+        {
+            log::info!(source:? = source, target:? = target; "Relinking");
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            match linked.mode {
+                LinkMode::Symlink => {
+                    remove_path(&linked.target)?;
+                    std::os::unix::fs::symlink(&source, &target).with_path(&target)?;
+                }
+                LinkMode::Copy => {
+                    if std::fs::rename(&linked.target, &target).is_err() {
+                        // The new target may live on another file system.
+                        copy_all(&linked.target, &target).with_path(&target)?;
+                        remove_path(&linked.target)?;
+                    }
+                }
+            }
+        }
+        (linked.source, linked.target) = (source, target);
+    }
+    save_state(prefix, &state)
+}
+
+/// Create the links of `names`, every entry of the link map if `names` is empty.
+/// Existing targets are reported as conflicts unless `adopt` is set.
+pub fn link(prefix: &Prefix, names: &[String], adopt: bool) -> Result<LinkReport> {
+    let map: HashMap<String, LinkConfig> = load_predefined_and_local(link_map_path(prefix))?;
+    let names = if names.is_empty() {
+        let mut names: Vec<_> = map.keys().cloned().collect();
+        names.sort();
+        names
+    } else {
+        names.to_vec()
+    };
+
+    let mut state = load_state(prefix)?;
+    let mut report = LinkReport::default();
+    for name in names {
+        let config =
+            map.get(&name).ok_or_else(|| Error::Config(format!("link {} does not exist", name)))?;
+        let linked = Linked {
+            source: prefix.dot().join(&config.source),
+            target: expand_target(prefix, &config.target)?,
+            mode: config.mode,
+        };
+        if !linked.source.exists() {
+            return Err(Error::Config(format!(
+                "source {:?} of link {} does not exist",
+                linked.source, name
+            )));
+        }
+
+        if linked.is_up_to_date() {
+            log::debug!(name = name; "Link is already up to date");
+            report.unchanged.push(name.clone());
+        } else {
+            if std::fs::symlink_metadata(&linked.target).is_ok() {
+                if !adopt {
+                    log::warn!(name = name, target:? = linked.target; "Target already exists");
+                    report.conflicts.push(linked.target);
+                    continue;
+                }
+                linked.adopt(prefix)?;
+                report.adopted.push(name.clone());
+            }
+            linked.create(prefix)?;
+            report.linked.push(name.clone());
+        }
+        state.insert(name, linked);
+    }
+    save_state(prefix, &state)?;
+    Ok(report)
+}
+
+/// Remove the links of `names` created by `dot link`, every one of them if `names` is empty.
+pub fn unlink(prefix: &Prefix, names: &[String], stale: bool) -> Result<UnlinkReport> {
+    let mut state = load_state(prefix)?;
+    let names: Vec<_> = if stale {
+        let path = link_map_path(prefix);
+        let map: HashMap<String, LinkConfig> =
+            if path.exists() { load_predefined_and_local(path)? } else { HashMap::new() };
+        state
+            .iter()
+            .filter(|(name, linked)| !map.contains_key(*name) || !linked.source.exists())
+            .map(|(name, _)| name.clone())
+            .collect()
+    } else if names.is_empty() {
+        state.keys().cloned().collect()
+    } else {
+        names.to_vec()
+    };
+
+    let mut report = UnlinkReport::default();
+    for name in names {
+        let linked = state
+            .remove(&name)
+            .ok_or_else(|| Error::Config(format!("link {} was not created by dot", name)))?;
+        if std::fs::symlink_metadata(&linked.target).is_err() {
+            log::debug!(name = name; "Link target is already gone");
+        } else if linked.is_up_to_date() {
+            linked.remove(prefix)?;
+        } else {
+            log::warn!(name = name, target:? = linked.target; "Target was modified, keeping it");
+            report.kept.push(linked.target);
+            continue;
+        }
+        report.removed.push(name);
+    }
+    save_state(prefix, &state)?;
+    Ok(report)
+}
+
+pub fn entry_link(prefix: &Prefix, args: LinkArgs) -> Result<()> {
+    let report = link(prefix, &args.names, args.adopt)?;
+    prefix.emit(&report)?;
+    if report.conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "targets {:?} already exist, use --adopt to move them into the dot repository",
+            report.conflicts
+        )))
+    }
+}
+
+pub fn entry_unlink(prefix: &Prefix, args: UnlinkArgs) -> Result<()> {
+    let report = unlink(prefix, &args.names, args.stale)?;
+    prefix.emit(&report)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn setup(prefix: &Prefix, map: &str) {
+        prefix.create_dir_all().unwrap();
+        std::fs::write(link_map_path(prefix), map).unwrap();
+        let source = prefix.dot().join("config").join("app");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("app.toml"), "key = 1\n").unwrap();
+    }
+
+    #[test]
+    fn test_link_and_unlink() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        setup(
+            &prefix,
+            r#"
+[app]
+source = "config/app"
+target = "~/.config/app"
+
+[copied]
+source = "config/app/app.toml"
+target = "~/app.toml"
+mode = "copy"
+"#,
+        );
+
+        let report = link(&prefix, &[], false).unwrap();
+        assert_eq!(report.linked, ["app", "copied"]);
+        let target = prefix.prefix().join(".config").join("app");
+        assert_eq!(std::fs::read_link(&target).unwrap(), prefix.dot().join("config").join("app"));
+        assert_eq!(std::fs::read_to_string(prefix.prefix().join("app.toml")).unwrap(), "key = 1\n");
+        assert_eq!(link(&prefix, &[], false).unwrap().unchanged, ["app", "copied"]);
+
+        std::fs::write(prefix.prefix().join("app.toml"), "key = 2\n").unwrap();
+        let report = unlink(&prefix, &[], false).unwrap();
+        assert_eq!(report.removed, ["app"]);
+        assert_eq!(report.kept, [prefix.prefix().join("app.toml")]);
+        assert!(std::fs::symlink_metadata(&target).is_err());
+        assert!(load_state(&prefix).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_link_conflict_and_adopt() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        setup(&prefix, "[app]\nsource = \"config/app\"\ntarget = \"~/.app\"\n");
+        let target = prefix.prefix().join(".app");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("app.toml"), "key = 3\n").unwrap();
+
+        assert_eq!(link(&prefix, &[], false).unwrap().conflicts(), [target.clone()]);
+        assert!(load_state(&prefix).unwrap().is_empty());
+
+        assert_eq!(link(&prefix, &[], true).unwrap().adopted, ["app"]);
+        assert!(std::fs::read_link(&target).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(prefix.dot().join("config").join("app").join("app.toml"))
+                .unwrap(),
+            "key = 3\n"
+        );
+    }
+
+    #[test]
+    fn test_unlink_stale() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        setup(&prefix, "[app]\nsource = \"config/app\"\ntarget = \"~/.app\"\n");
+        link(&prefix, &[], false).unwrap();
+
+        assert!(unlink(&prefix, &[], true).unwrap().removed.is_empty());
+        std::fs::write(link_map_path(&prefix), "").unwrap();
+        assert_eq!(unlink(&prefix, &[], true).unwrap().removed, ["app"]);
+        assert!(std::fs::symlink_metadata(prefix.prefix().join(".app")).is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 use dot::{Cli, entry};
 
-fn main() {
+fn main() -> ExitCode {
     entry(Cli::parse())
 }
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+use clap::{Args, CommandFactory};
+
+use crate::Cli;
+use crate::error::{Result, WithPath};
+use crate::prefix::Prefix;
+
+#[derive(Debug, Args)]
+pub struct ManArgs {
+    /// Write one man page per subcommand into this directory instead of printing the main page.
+    #[arg(short, long)]
+    pub dir: Option<PathBuf>,
+}
+
+pub fn entry_man(prefix: &Prefix, args: ManArgs) -> Result<()> {
+    let command = Cli::command();
+    if let Some(dir) = args.dir {
+        if prefix.dry_run(format_args!("write man pages to {:?}", dir)) {
+            return Ok(());
+        }
+        log::info!(dir:? = dir; "Generating man pages");
+        std::fs::create_dir_all(&dir).with_path(&dir)?;
+        clap_mangen::generate_to(command, &dir).with_path(&dir)
+    } else {
+        clap_mangen::Man::new(command).render(&mut std::io::stdout()).with_path("stdout")
+    }
+}
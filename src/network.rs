@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use git2::{FetchOptions, ProxyOptions};
+use reqwest::blocking::Client;
+use reqwest::{Certificate, NoProxy, Proxy};
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::{Error, Result, WithContext, WithPath};
+use crate::prefix::Prefix;
+use crate::utils::load_toml;
+
+/// Root certificate bundles of the common distributions, after `$SSL_CERT_FILE`.
+const SYSTEM_CA_BUNDLES: [&str; 5] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+    "/usr/local/etc/openssl/cert.pem",
+];
+
+#[derive(Deserialize, Debug, Default)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct NetworkConfig {
+    /// Proxy used for every request. `$HTTPS_PROXY`/`$NO_PROXY` are used if this is empty.
+    proxy: Option<String>,
+    /// Comma separated hosts that bypass `proxy`.
+    no_proxy: Option<String>,
+    /// Extra PEM bundle added to the trusted root certificates.
+    ca_bundle: Option<PathBuf>,
+    /// Url prefix rewrite rules, the longest matching prefix wins.
+    #[serde(default)]
+    rewrite: HashMap<String, String>,
+}
+
+impl NetworkConfig {
+    fn from_path(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if path.exists() {
+            log::debug!(path:? = path; "Loading network config");
+            Ok(Some(load_toml(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn load(prefix: &Prefix) -> Result<Self> {
+        let path = prefix.config_network().join("network.toml");
+        let config = Self::from_path(&path)?.unwrap_or_default();
+        Ok(if let Some(local) = Self::from_path(path.with_file_name(".local.toml"))? {
+            Self {
+                proxy: local.proxy.or(config.proxy),
+                no_proxy: local.no_proxy.or(config.no_proxy),
+                ca_bundle: local.ca_bundle.or(config.ca_bundle),
+                rewrite: config.rewrite.into_iter().chain(local.rewrite).collect(),
+            }
+        } else {
+            config
+        })
+    }
+
+    pub fn rewrite(&self, url: &str) -> String {
+        if let Some((from, to)) = self
+            .rewrite
+            .iter()
+            .filter(|(from, _)| url.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+        {
+            let new_url = format!("{}{}", to, &url[from.len()..]);
+            log::debug!(old:% = url, new:% = new_url; "Rewriting url");
+            new_url
+        } else {
+            url.to_owned()
+        }
+    }
+
+    pub fn client(&self) -> Result<Client> {
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy {
+            log::debug!(proxy:% = proxy; "Using proxy");
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .map_err(|e| Error::Config(format!("invalid proxy {}: {}", proxy, e)))?
+                    .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)),
+            );
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            log::debug!(path:? = ca_bundle; "Adding ca bundle");
+            for cert in
+                Certificate::from_pem_bundle(&std::fs::read(ca_bundle).with_path(ca_bundle)?)
+                    .map_err(|e| {
+                        Error::Config(format!("invalid ca bundle {:?}: {}", ca_bundle, e))
+                    })?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        builder.build().map_err(|e| Error::Config(format!("can not build http client: {}", e)))
+    }
+
+    /// Whether `url` bypasses `proxy` because its host is listed in `no_proxy`.
+    fn bypass_proxy(&self, url: &str) -> bool {
+        let Some(host) = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_owned))
+        else {
+            return false;
+        };
+        self.no_proxy.iter().flat_map(|no_proxy| no_proxy.split(',')).any(|entry| {
+            let entry = entry.trim().trim_start_matches('.');
+            !entry.is_empty()
+                && (entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+        })
+    }
+
+    /// Write the system root certificates followed by `ca_bundle` into the cache, libgit2
+    /// only trusts a single bundle once one is set.
+    fn write_ca_bundle(&self, prefix: &Prefix) -> Result<Option<PathBuf>> {
+        let Some(ca_bundle) = &self.ca_bundle else {
+            return Ok(None);
+        };
+        let path = prefix.cache().join("ca-bundle.pem");
+        if prefix.dry_run(format_args!("write {:?} with {:?}", path, ca_bundle)) {
+            return Ok(None);
+        }
+        let mut content = match std::env::var_os("SSL_CERT_FILE")
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(SYSTEM_CA_BUNDLES.iter().map(PathBuf::from))
+            .find(|path| path.is_file())
+        {
+            Some(system) => {
+                log::debug!(path:? = system; "Using system ca bundle");
+                std::fs::read(&system).with_path(&system)?
+            }
+            None => {
+                log::warn!("Can not find the system ca bundle, only trusting the extra one");
+                vec![]
+            }
+        };
+        if !content.is_empty() && !content.ends_with(b"\n") {
+            content.push(b'\n');
+        }
+        content.extend(std::fs::read(ca_bundle).with_path(ca_bundle)?);
+        std::fs::create_dir_all(prefix.cache()).with_path(prefix.cache())?;
+        std::fs::write(&path, content).with_path(&path)?;
+        Ok(Some(path))
+    }
+
+    /// Set up the certificates of libgit2, called once before any git operation.
+    pub fn setup_git(&self, prefix: &Prefix) -> Result<()> {
+        if let Some(path) = self.write_ca_bundle(prefix)? {
+            log::debug!(path:? = path; "Adding ca bundle");
+            // Safety: this runs on the main thread before any git operation.
+            unsafe { git2::opts::set_ssl_cert_file(&path) }
+                .with_context(format!("can not use ca bundle {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    pub fn fetch_options(&self, url: &str) -> FetchOptions<'static> {
+        let mut po = ProxyOptions::new();
+        match &self.proxy {
+            Some(proxy) if !self.bypass_proxy(url) => {
+                log::debug!(proxy:% = proxy; "Using proxy");
+                po.url(proxy);
+            }
+            Some(_) => log::debug!(url:% = url; "Bypassing proxy"),
+            None => {
+                po.auto();
+            }
+        }
+
+        let mut fo = FetchOptions::new();
+        fo.proxy_options(po);
+        fo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_rewrite_longest_prefix() {
+        let config = NetworkConfig {
+            rewrite: [
+                ("https://github.com/".to_owned(), "https://mirror.internal/github/".to_owned()),
+                ("https://github.com/vnghia/".to_owned(), "https://mirror.internal/me/".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.rewrite("https://github.com/starship/starship.git"),
+            "https://mirror.internal/github/starship/starship.git"
+        );
+        assert_eq!(
+            config.rewrite("https://github.com/vnghia/dot.git"),
+            "https://mirror.internal/me/dot.git"
+        );
+        assert_eq!(config.rewrite("https://gitlab.com/a/b.git"), "https://gitlab.com/a/b.git");
+    }
+
+    #[test]
+    fn test_load_non_existent() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        assert_eq!(NetworkConfig::load(&prefix).unwrap(), NetworkConfig::default());
+    }
+
+    #[test]
+    fn test_load_predefined_and_local() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        std::fs::write(
+            prefix.config_network().join("network.toml"),
+            r#"
+proxy = "http://proxy:8080"
+
+[rewrite]
+"https://github.com/" = "https://mirror.internal/github/"
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            prefix.config_network().join(".local.toml"),
+            r#"
+ca_bundle = "/etc/ssl/corp.pem"
+
+[rewrite]
+"https://gitlab.com/" = "https://mirror.internal/gitlab/"
+"#,
+        )
+        .unwrap();
+        assert_eq!(NetworkConfig::load(&prefix).unwrap(), NetworkConfig {
+            proxy: Some("http://proxy:8080".into()),
+            no_proxy: None,
+            ca_bundle: Some("/etc/ssl/corp.pem".into()),
+            rewrite: [
+                ("https://github.com/".into(), "https://mirror.internal/github/".into()),
+                ("https://gitlab.com/".into(), "https://mirror.internal/gitlab/".into()),
+            ]
+            .into_iter()
+            .collect(),
+        });
+    }
+
+    #[test]
+    fn test_bypass_proxy() {
+        let config = NetworkConfig {
+            proxy: Some("http://proxy:8080".into()),
+            no_proxy: Some("localhost, .internal,example.com".into()),
+            ..Default::default()
+        };
+        assert!(config.bypass_proxy("https://mirror.internal/github/dot.git"));
+        assert!(config.bypass_proxy("https://git.example.com/dot.git"));
+        assert!(config.bypass_proxy("http://localhost:3000/dot.git"));
+        assert!(!config.bypass_proxy("https://github.com/vnghia/dot.git"));
+        assert!(!config.bypass_proxy("https://notexample.com/dot.git"));
+        assert!(!NetworkConfig::default().bypass_proxy("https://github.com/vnghia/dot.git"));
+    }
+
+    #[test]
+    fn test_write_ca_bundle() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        assert_eq!(NetworkConfig::default().write_ca_bundle(&prefix).unwrap(), None);
+
+        let extra = temp_dir.path().join("corp.pem");
+        std::fs::write(&extra, "corp\n").unwrap();
+        let config = NetworkConfig { ca_bundle: Some(extra), ..Default::default() };
+        let path = config.write_ca_bundle(&prefix).unwrap().unwrap();
+        assert_eq!(path, prefix.cache().join("ca-bundle.pem"));
+        // The extra bundle comes after the system one, if the system has one.
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("corp\n"));
+    }
+}
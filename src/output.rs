@@ -0,0 +1,33 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Output {
+    /// Human readable output, only some subcommands print anything.
+    #[default]
+    Text,
+    /// One JSON document per invocation on stdout.
+    Json,
+}
+
+/// Structured result of a subcommand.
+pub trait Report: Serialize {
+    /// Print the human readable form of this report.
+    fn print(&self) {}
+}
+
+impl Output {
+    pub fn emit(self, report: &impl Report) -> Result<()> {
+        match self {
+            Output::Text => report.print(),
+            Output::Json => println!(
+                "{}",
+                serde_json::to_string(report)
+                    .map_err(|e| Error::Config(format!("can not serialize report: {}", e)))?
+            ),
+        }
+        Ok(())
+    }
+}
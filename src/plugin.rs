@@ -0,0 +1,480 @@
+use std::path::{Component, Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result, WithContext, WithPath};
+use crate::git::{checkout_ref, clone, pull};
+use crate::init::{remember_ref, remembered_ref};
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::utils::load_toml;
+
+/// Zsh file sourcing the plugins, generated inside `Prefix::plugin()`.
+const PLUGINS_SCRIPT: &str = "plugins.zsh";
+
+#[derive(Debug, Args)]
+pub struct PluginArgs {
+    #[command(subcommand)]
+    pub command: PluginCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PluginCommand {
+    /// Clone the plugins which are missing or pinned to another ref.
+    Install(PluginNamesArgs),
+    /// Pull the plugins, following their pinned ref if any.
+    Update(PluginNamesArgs),
+    /// List the plugins of `plugins.toml` and their checkout.
+    List,
+    /// Remove cloned plugins, default to the ones no longer in `plugins.toml`.
+    Remove(PluginNamesArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct PluginNamesArgs {
+    /// Names of the plugins, every plugin of `plugins.toml` if none is supplied.
+    pub names: Vec<String>,
+}
+
+/// `config/plugin/plugins.toml`, the plugins of `.local.toml` are appended.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PluginsConfig {
+    /// Compile the sourced files with `zcompile`.
+    zcompile: Option<bool>,
+    /// Plugins sourced in the declared order.
+    plugin: Vec<Plugin>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Plugin {
+    name: String,
+    /// Url of the git repository.
+    repo: String,
+    /// Branch, tag or commit to check out. Default to the default branch of the repository.
+    #[serde(default, rename = "ref")]
+    git_ref: Option<String>,
+    /// File of the plugin to source. Default to `<name>.plugin.zsh` or `<name>.zsh`.
+    #[serde(default)]
+    source: Option<String>,
+    /// Files of the dot repository sourced before the plugin, such as its configuration.
+    #[serde(default)]
+    before: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PluginReport {
+    installed: Vec<String>,
+    updated: Vec<String>,
+    removed: Vec<String>,
+    /// The generated zsh file, if it was rewritten.
+    script: Option<PathBuf>,
+}
+
+impl PluginReport {
+    pub fn installed(&self) -> &[String] {
+        &self.installed
+    }
+
+    pub fn updated(&self) -> &[String] {
+        &self.updated
+    }
+}
+
+impl Report for PluginReport {
+    fn print(&self) {
+        for name in &self.installed {
+            println!("Installed {}", name);
+        }
+        for name in &self.updated {
+            println!("Updated {}", name);
+        }
+        for name in &self.removed {
+            println!("Removed {}", name);
+        }
+        if let Some(script) = &self.script {
+            println!("Regenerated {}", script.display());
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ListedPlugin {
+    name: String,
+    repo: String,
+    git_ref: Option<String>,
+    /// Checked out commit, `None` if the plugin is not cloned.
+    head: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PluginListReport {
+    plugins: Vec<ListedPlugin>,
+}
+
+impl Report for PluginListReport {
+    fn print(&self) {
+        for plugin in &self.plugins {
+            let head = match &plugin.head {
+                Some(head) => format!("{:.7}", head),
+                None => "not installed".to_owned(),
+            };
+            let git_ref = plugin.git_ref.as_deref().unwrap_or("default branch");
+            println!("{} {} ({}) {}", plugin.name, git_ref, head, plugin.repo);
+        }
+    }
+}
+
+fn config_path(prefix: &Prefix) -> PathBuf {
+    prefix.config_plugin().join("plugins.toml")
+}
+
+fn load(prefix: &Prefix) -> Result<PluginsConfig> {
+    let path = config_path(prefix);
+    let mut config = PluginsConfig::default();
+    for path in [path.clone(), path.with_file_name(".local.toml")] {
+        if path.exists() {
+            let local: PluginsConfig = load_toml(&path)?;
+            for plugin in &local.plugin {
+                check_name(&plugin.name)?;
+            }
+            config.zcompile = local.zcompile.or(config.zcompile);
+            config.plugin.extend(local.plugin);
+        }
+    }
+    Ok(config)
+}
+
+/// Reject names which are not a single directory of `Prefix::plugin()`, such as `..`.
+fn check_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(Error::Config(format!("invalid plugin name {:?}", name))),
+    }
+}
+
+fn plugin_dir(prefix: &Prefix, name: &str) -> PathBuf {
+    prefix.plugin().join(name)
+}
+
+fn open(path: &Path) -> Result<Repository> {
+    Repository::open(path).with_context(format!("can not open git repository at {:?}", path))
+}
+
+fn head(repo: &Repository) -> Option<String> {
+    repo.head().ok().and_then(|head| head.target()).map(|id| id.to_string())
+}
+
+/// Plugins of `names`, every plugin if it is empty.
+fn select<'c>(config: &'c PluginsConfig, names: &[String]) -> Result<Vec<&'c Plugin>> {
+    if let Some(name) =
+        names.iter().find(|name| !config.plugin.iter().any(|plugin| plugin.name == **name))
+    {
+        return Err(Error::Config(format!("plugin {} is not in plugins.toml", name)));
+    }
+    Ok(config
+        .plugin
+        .iter()
+        .filter(|plugin| names.is_empty() || names.contains(&plugin.name))
+        .collect())
+}
+
+impl Plugin {
+    /// File of the cloned plugin to source, `None` if it can not be found.
+    fn source_path(&self, prefix: &Prefix) -> Option<PathBuf> {
+        let dir = plugin_dir(prefix, &self.name);
+        match &self.source {
+            Some(source) => Some(dir.join(source)),
+            None => [format!("{}.plugin.zsh", self.name), format!("{}.zsh", self.name)]
+                .into_iter()
+                .map(|file| dir.join(file))
+                .find(|path| path.exists()),
+        }
+        .filter(|path| path.exists())
+    }
+
+    /// Clone the plugin or check out its pinned ref, return whether anything changed.
+    fn install(&self, prefix: &Prefix) -> Result<bool> {
+        let dir = plugin_dir(prefix, &self.name);
+        if !dir.exists() {
+            log::info!(plugin = self.name, repo = self.repo; "Cloning plugin");
+            clone(prefix, &self.repo, &dir, true)?;
+            if let Some(git_ref) = &self.git_ref
+                && !prefix.dry_run(format_args!("check out {} in {:?}", git_ref, dir))
+            {
+                let repo = open(&dir)?;
+                remember_ref(prefix, &repo, git_ref)?;
+                checkout_ref(prefix, &repo, git_ref, true)?;
+            }
+            return Ok(true);
+        }
+
+        let repo = open(&dir)?;
+        match &self.git_ref {
+            Some(git_ref) if remembered_ref(&repo).as_ref() != Some(git_ref) => {
+                log::info!(plugin = self.name, git_ref = git_ref; "Checking out plugin");
+                remember_ref(prefix, &repo, git_ref)?;
+                checkout_ref(prefix, &repo, git_ref, true)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Pull the plugin, return whether its checkout moved.
+    fn update(&self, prefix: &Prefix) -> Result<bool> {
+        let dir = plugin_dir(prefix, &self.name);
+        if !dir.exists() {
+            log::warn!(plugin = self.name; "Plugin is not installed, run `dot plugin install`");
+            return Ok(false);
+        }
+        let repo = open(&dir)?;
+        let before = head(&repo);
+        match &self.git_ref {
+            Some(git_ref) => {
+                remember_ref(prefix, &repo, git_ref)?;
+                checkout_ref(prefix, &repo, git_ref, true)?;
+            }
+            None => {
+                let Some(branch) = repo
+                    .head()
+                    .ok()
+                    .filter(|head| head.is_branch())
+                    .and_then(|head| head.shorthand().map(str::to_owned))
+                else {
+                    log::warn!(plugin = self.name; "Plugin is detached without a ref, skipping");
+                    return Ok(false);
+                };
+                pull(prefix, &repo, None, Some(&branch), true)?;
+            }
+        }
+        Ok(head(&repo) != before)
+    }
+}
+
+fn script_content(prefix: &Prefix, config: &PluginsConfig) -> String {
+    let mut content = "# AUTO GENERATED FILE. DO NOT EDIT\n".to_string();
+    for plugin in &config.plugin {
+        let Some(source) = plugin.source_path(prefix) else {
+            continue;
+        };
+        content += &format!("\n# {}\n", plugin.name);
+        for before in &plugin.before {
+            content += &format!("source \"$DOTDIR/{}\"\n", before);
+        }
+        let relative = source.strip_prefix(prefix.plugin()).unwrap_or(&source);
+        content += &format!("source \"$LOCALDIR/plugin/{}\"\n", relative.display());
+    }
+    content
+}
+
+fn zcompile(prefix: &Prefix, paths: &[PathBuf]) -> Result<()> {
+    let mut command = std::process::Command::new("zsh");
+    command.args(["-c", "for file in \"$@\"; do zcompile \"$file\"; done", "zsh"]).args(paths);
+    if prefix.dry_run(format_args!("run {:?}", command)) {
+        return Ok(());
+    }
+    prefix.run(&mut command)
+}
+
+/// Write the zsh file sourcing the cloned plugins, return it if it was rewritten.
+/// The plugins are compiled again if the file was rewritten or `changed` is set because a
+/// plugin checkout moved.
+fn generate(prefix: &Prefix, config: &PluginsConfig, changed: bool) -> Result<Option<PathBuf>> {
+    let path = prefix.plugin().join(PLUGINS_SCRIPT);
+    let content = script_content(prefix, config);
+    let rewritten = !std::fs::read_to_string(&path).is_ok_and(|current| current == content);
+    if rewritten {
+        log::info!(path:? = path; "Generating plugins script");
+        if !prefix.dry_run(format_args!("write {:?}:\n{}", path, content)) {
+            std::fs::create_dir_all(prefix.plugin()).with_path(prefix.plugin())?;
+            std::fs::write(&path, content).with_path(&path)?;
+        }
+    }
+
+    if (rewritten || changed) && config.zcompile.unwrap_or(false) {
+        let mut paths: Vec<_> =
+            config.plugin.iter().filter_map(|plugin| plugin.source_path(prefix)).collect();
+        paths.push(path.clone());
+        if let Err(e) = zcompile(prefix, &paths) {
+            log::warn!(error:% = e; "Can not zcompile the plugins");
+        }
+    }
+    Ok(rewritten.then_some(path))
+}
+
+/// Clone the plugins of `names` which are missing or pinned to another ref.
+pub fn install(prefix: &Prefix, names: &[String]) -> Result<PluginReport> {
+    let config = load(prefix)?;
+    let mut report = PluginReport::default();
+    for plugin in select(&config, names)? {
+        if plugin.install(prefix)? {
+            report.installed.push(plugin.name.clone());
+        }
+    }
+    report.script = generate(prefix, &config, !report.installed.is_empty())?;
+    Ok(report)
+}
+
+/// Pull the plugins of `names` following a branch and check out the pinned ones.
+pub fn update(prefix: &Prefix, names: &[String]) -> Result<PluginReport> {
+    let config = load(prefix)?;
+    let mut report = PluginReport::default();
+    for plugin in select(&config, names)? {
+        if plugin.update(prefix)? {
+            report.updated.push(plugin.name.clone());
+        }
+    }
+    report.script = generate(prefix, &config, !report.updated.is_empty())?;
+    Ok(report)
+}
+
+fn list(prefix: &Prefix) -> Result<PluginListReport> {
+    let plugins = load(prefix)?
+        .plugin
+        .into_iter()
+        .map(|plugin| ListedPlugin {
+            head: Repository::open(plugin_dir(prefix, &plugin.name))
+                .ok()
+                .and_then(|repo| head(&repo)),
+            name: plugin.name,
+            repo: plugin.repo,
+            git_ref: plugin.git_ref,
+        })
+        .collect();
+    Ok(PluginListReport { plugins })
+}
+
+/// Remove the clones of `names`, or the ones no longer declared if it is empty.
+fn remove(prefix: &Prefix, names: &[String]) -> Result<PluginReport> {
+    let config = load(prefix)?;
+    let mut removed = vec![];
+    if names.is_empty() {
+        let plugin_dir = prefix.plugin();
+        if plugin_dir.exists() {
+            for entry in std::fs::read_dir(&plugin_dir).with_path(&plugin_dir)? {
+                let path = entry.with_path(&plugin_dir)?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                if path.is_dir() && !config.plugin.iter().any(|plugin| plugin.name == name) {
+                    removed.push(name);
+                }
+            }
+        }
+        removed.sort();
+    } else {
+        for name in names {
+            check_name(name)?;
+        }
+        removed = names.to_vec();
+    }
+
+    removed.retain(|name| plugin_dir(prefix, name).exists());
+    for name in &removed {
+        let dir = plugin_dir(prefix, name);
+        if !prefix.dry_run(format_args!("remove directory {:?}", dir)) {
+            log::info!(plugin = name, path:? = dir; "Removing plugin");
+            std::fs::remove_dir_all(&dir).with_path(&dir)?;
+        }
+    }
+    Ok(PluginReport { removed, script: generate(prefix, &config, false)?, ..Default::default() })
+}
+
+pub fn entry_plugin(prefix: &Prefix, args: PluginArgs) -> Result<()> {
+    match args.command {
+        PluginCommand::Install(args) => prefix.emit(&install(prefix, &args.names)?),
+        PluginCommand::Update(args) => prefix.emit(&update(prefix, &args.names)?),
+        PluginCommand::List => prefix.emit(&list(prefix)?),
+        PluginCommand::Remove(args) => prefix.emit(&remove(prefix, &args.names)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::utils::commit;
+
+    #[test]
+    fn test_install_and_remove() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let upstream = Repository::init(temp_dir.path().join("zsh-test")).unwrap();
+        let first = commit(&upstream, "zsh-test.plugin.zsh", "");
+        upstream.tag_lightweight("v1", &upstream.find_object(first, None).unwrap(), false).unwrap();
+        commit(&upstream, "second", "");
+        std::fs::write(
+            config_path(&prefix),
+            format!(
+                "[[plugin]]\nname = \"zsh-test\"\nrepo = {:?}\nref = \"v1\"\nbefore = \
+                 [\"shell/zsh/test.zsh\"]\n",
+                upstream.path().to_str().unwrap()
+            ),
+        )
+        .unwrap();
+
+        let report = install(&prefix, &[]).unwrap();
+        assert_eq!(report.installed, ["zsh-test"]);
+        let script = prefix.plugin().join(PLUGINS_SCRIPT);
+        assert_eq!(report.script.as_ref(), Some(&script));
+        assert_eq!(
+            std::fs::read_to_string(&script).unwrap(),
+            "# AUTO GENERATED FILE. DO NOT EDIT\n\n# zsh-test\nsource \
+             \"$DOTDIR/shell/zsh/test.zsh\"\nsource \
+             \"$LOCALDIR/plugin/zsh-test/zsh-test.plugin.zsh\"\n"
+        );
+        let repo = open(&plugin_dir(&prefix, "zsh-test")).unwrap();
+        assert_eq!(head(&repo), Some(first.to_string()));
+        assert!(!plugin_dir(&prefix, "zsh-test").join("second").exists());
+
+        let report = install(&prefix, &[]).unwrap();
+        assert!(report.installed.is_empty());
+        assert!(report.script.is_none());
+        assert!(matches!(install(&prefix, &["unknown".to_owned()]), Err(Error::Config(_))));
+
+        for name in ["..", "../bin", "/", "."] {
+            assert!(matches!(remove(&prefix, &[name.to_owned()]), Err(Error::Config(_))));
+        }
+        assert!(prefix.bin().exists());
+
+        std::fs::write(config_path(&prefix), "").unwrap();
+        let report = remove(&prefix, &[]).unwrap();
+        assert_eq!(report.removed, ["zsh-test"]);
+        assert!(!plugin_dir(&prefix, "zsh-test").exists());
+        assert_eq!(
+            std::fs::read_to_string(&script).unwrap(),
+            "# AUTO GENERATED FILE. DO NOT EDIT\n"
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let upstream = Repository::init(temp_dir.path().join("zsh-test")).unwrap();
+        commit(&upstream, "zsh-test.plugin.zsh", "");
+        std::fs::write(
+            config_path(&prefix),
+            format!(
+                "[[plugin]]\nname = \"zsh-test\"\nrepo = {:?}\n",
+                upstream.path().to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        install(&prefix, &[]).unwrap();
+
+        let report = update(&prefix, &[]).unwrap();
+        assert!(report.updated().is_empty());
+        let second = commit(&upstream, "second", "");
+        let report = update(&prefix, &[]).unwrap();
+        assert_eq!(report.updated(), ["zsh-test"]);
+        let repo = open(&plugin_dir(&prefix, "zsh-test")).unwrap();
+        assert_eq!(head(&repo), Some(second.to_string()));
+    }
+}
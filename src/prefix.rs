@@ -1,56 +1,247 @@
+use std::fmt::Display;
 use std::path::{Path, PathBuf};
 
 use homedir::my_home;
+use serde::{Deserialize, Serialize};
 
 use crate::constant::env::DOTDIR_KEY;
+use crate::error::{Error, Result, WithPath, run};
+use crate::output::{Output, Report};
+
+/// Where the dot environment lives inside the prefix.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Everything under `~/.dot`, the local files under `~/.dot/.local`.
+    #[default]
+    Legacy,
+    /// The XDG base directories, with the binaries in `~/.local/bin`.
+    Xdg,
+}
+
+/// Base directories of the XDG specification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XdgDir {
+    Config,
+    Data,
+    State,
+    Cache,
+}
+
+impl XdgDir {
+    pub const ALL: [XdgDir; 4] = [XdgDir::Config, XdgDir::Data, XdgDir::State, XdgDir::Cache];
+
+    pub fn var(self) -> &'static str {
+        match self {
+            XdgDir::Config => "XDG_CONFIG_HOME",
+            XdgDir::Data => "XDG_DATA_HOME",
+            XdgDir::State => "XDG_STATE_HOME",
+            XdgDir::Cache => "XDG_CACHE_HOME",
+        }
+    }
+
+    /// Default of the directory relative to the home directory.
+    fn fallback(self) -> &'static str {
+        match self {
+            XdgDir::Config => ".config",
+            XdgDir::Data => ".local/share",
+            XdgDir::State => ".local/state",
+            XdgDir::Cache => ".cache",
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct Prefix(PathBuf);
+pub struct Prefix {
+    root: PathBuf,
+    layout: Layout,
+    /// Whether the `$XDG_*` variables apply, they are ignored for a prefix given on the
+    /// command line.
+    xdg_env: bool,
+    dry_run: bool,
+    output: Output,
+}
 
 impl Prefix {
     pub const SSH_CONFIG_DIR_NAME: &'static str = "config.d";
 
-    pub fn new(prefix: Option<PathBuf>) -> Self {
+    pub const LEGACY_DOT_DIR_NAME: &'static str = ".dot";
+
+    pub fn new(prefix: Option<PathBuf>) -> Result<Self> {
+        let xdg_env = prefix.is_none();
         let prefix = if let Some(prefix) = prefix {
             log::debug!("Prefix from command line");
             prefix
-        } else if let Ok(dot_dir) = std::env::var(DOTDIR_KEY) {
+        } else if let Ok(dot_dir) = std::env::var(DOTDIR_KEY)
+            && Path::new(&dot_dir).file_name().is_some_and(|name| name == Self::LEGACY_DOT_DIR_NAME)
+        {
             log::debug!(dot_dir:% = dot_dir; "Prefix from `$DOTDIR` environment");
-            Path::new(&dot_dir).parent().unwrap().into()
+            Path::new(&dot_dir)
+                .parent()
+                .ok_or_else(|| Error::Config(format!("`${}` has no parent directory", DOTDIR_KEY)))?
+                .into()
         } else {
             log::debug!("Prefix from home directory");
-            my_home().unwrap().unwrap()
+            my_home()
+                .ok()
+                .flatten()
+                .ok_or_else(|| Error::Config("can not find the home directory".into()))?
+        };
+        let prefix = prefix.canonicalize().with_path(&prefix)?;
+
+        let mut prefix = Self {
+            root: prefix,
+            layout: Layout::Legacy,
+            xdg_env,
+            dry_run: false,
+            output: Output::default(),
+        };
+        // An existing legacy tree wins until `dot init` migrates it.
+        if !prefix.dot().exists() && prefix.to_layout(Layout::Xdg).dot().exists() {
+            prefix.layout = Layout::Xdg;
         }
-        .canonicalize()
-        .unwrap();
-        log::info!(prefix:? = prefix; "Resolved");
+        log::info!(prefix:? = prefix.root, layout:? = prefix.layout; "Resolved");
+        Ok(prefix)
+    }
 
-        Self(prefix)
+    /// The same prefix with the directories of `layout`.
+    pub fn to_layout(&self, layout: Layout) -> Self {
+        Self {
+            root: self.root.clone(),
+            layout,
+            xdg_env: self.xdg_env,
+            dry_run: self.dry_run,
+            output: self.output,
+        }
     }
 
-    pub fn create_dir_all(&self) {
-        std::fs::create_dir_all(self.ssh()).unwrap();
-        std::fs::create_dir_all(self.ssh_config()).unwrap();
-        std::fs::create_dir_all(self.code()).unwrap();
-        std::fs::create_dir_all(self.local()).unwrap();
-        std::fs::create_dir_all(self.bin()).unwrap();
-        std::fs::create_dir_all(self.skm()).unwrap();
+    /// The same layout under `root`. The `$XDG_*` variables describe the current prefix so
+    /// they are ignored for the new one.
+    pub fn to_root(&self, root: PathBuf) -> Self {
+        Self {
+            root,
+            layout: self.layout,
+            xdg_env: false,
+            dry_run: self.dry_run,
+            output: self.output,
+        }
+    }
 
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Print the result of a subcommand in the requested output format.
+    pub fn emit(&self, report: &impl Report) -> Result<()> {
+        self.output.emit(report)
+    }
+
+    /// Run the command to completion, its stdout goes to stderr unless the output is text so that
+    /// the report stays the only thing on stdout.
+    pub fn run(&self, command: &mut std::process::Command) -> Result<()> {
+        if self.output != Output::Text {
+            command.stdout(std::io::stderr());
+        }
+        run(command)
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Report `action` instead of performing it if `--dry-run` is set.
+    /// Return whether the action should be skipped.
+    pub fn dry_run(&self, action: impl Display) -> bool {
+        if self.dry_run {
+            // Stdout is reserved for the report in json mode.
+            match self.output {
+                Output::Text => println!("[dry-run] {}", action),
+                Output::Json => eprintln!("[dry-run] {}", action),
+            }
+        }
+        self.dry_run
+    }
+
+    pub fn create_dir_all(&self) -> Result<()> {
+        let mut dirs = vec![
+            self.ssh(),
+            self.ssh_config(),
+            self.code(),
+            self.local(),
+            self.bin(),
+            self.skm(),
+            self.hook(),
+            self.completion(),
+            self.plugin(),
+            self.state(),
+            self.cache(),
+        ];
         if cfg!(test) {
-            std::fs::create_dir_all(self.bin()).unwrap();
+            dirs.extend([
+                self.config_git(),
+                self.config_ssh(),
+                self.config_binary(),
+                self.config_network(),
+                self.config_link(),
+                self.config_template(),
+                self.config_secret(),
+                self.config_plugin(),
+            ]);
+        }
 
-            std::fs::create_dir_all(self.config_git()).unwrap();
-            std::fs::create_dir_all(self.config_ssh()).unwrap();
-            std::fs::create_dir_all(self.config_binary()).unwrap();
+        for dir in dirs {
+            if dir.exists() || self.dry_run(format_args!("create directory {:?}", dir)) {
+                continue;
+            }
+            std::fs::create_dir_all(&dir).with_path(&dir)?;
         }
+        Ok(())
     }
 
     pub fn prefix(&self) -> &Path {
-        &self.0
+        &self.root
+    }
+
+    /// `path` relative to `home`, the way a tool refers to the home directory, if the prefix is
+    /// the home directory of the current user and contains it. Generated files stay valid when
+    /// the home directory is moved this way. Any other path is kept absolute.
+    pub fn home_relative(&self, path: &Path, home: &str) -> String {
+        let is_home = my_home()
+            .ok()
+            .flatten()
+            .and_then(|home| home.canonicalize().ok())
+            .is_some_and(|home| home == self.prefix());
+        match path.strip_prefix(self.prefix()) {
+            Ok(rest) if is_home && rest.as_os_str().is_empty() => home.to_owned(),
+            Ok(rest) if is_home => format!("{}/{}", home, rest.display()),
+            _ => path.display().to_string(),
+        }
+    }
+
+    /// `$XDG_*` directory of `dir`, its default inside the prefix if the variable is not set.
+    pub fn xdg(&self, dir: XdgDir) -> PathBuf {
+        std::env::var_os(dir.var())
+            .filter(|_| self.xdg_env)
+            .map(PathBuf::from)
+            .filter(|base| base.is_absolute())
+            .unwrap_or_else(|| self.prefix().join(dir.fallback()))
     }
 
     pub fn dot(&self) -> PathBuf {
-        self.prefix().join(".dot")
+        match self.layout {
+            Layout::Legacy => self.prefix().join(Self::LEGACY_DOT_DIR_NAME),
+            Layout::Xdg => self.xdg(XdgDir::Config).join("dot"),
+        }
     }
 
     pub fn ssh(&self) -> PathBuf {
@@ -66,17 +257,51 @@ impl Prefix {
     }
 
     pub fn local(&self) -> PathBuf {
-        self.dot().join(".local")
+        match self.layout {
+            Layout::Legacy => self.dot().join(".local"),
+            Layout::Xdg => self.xdg(XdgDir::Data).join("dot"),
+        }
+    }
+
+    /// Files remembering what previous runs did.
+    pub fn state(&self) -> PathBuf {
+        match self.layout {
+            Layout::Legacy => self.local(),
+            Layout::Xdg => self.xdg(XdgDir::State).join("dot"),
+        }
+    }
+
+    /// Files which can be regenerated at any time.
+    pub fn cache(&self) -> PathBuf {
+        match self.layout {
+            Layout::Legacy => self.local(),
+            Layout::Xdg => self.xdg(XdgDir::Cache).join("dot"),
+        }
     }
 
     pub fn bin(&self) -> PathBuf {
-        self.local().join("bin")
+        match self.layout {
+            Layout::Legacy => self.local().join("bin"),
+            Layout::Xdg => self.prefix().join(".local").join("bin"),
+        }
     }
 
     pub fn skm(&self) -> PathBuf {
         self.local().join("skm")
     }
 
+    pub fn hook(&self) -> PathBuf {
+        self.cache().join("hook")
+    }
+
+    pub fn completion(&self) -> PathBuf {
+        self.local().join("completion")
+    }
+
+    pub fn plugin(&self) -> PathBuf {
+        self.local().join("plugin")
+    }
+
     pub fn config(&self) -> PathBuf {
         self.dot().join("config")
     }
@@ -92,11 +317,54 @@ impl Prefix {
     pub fn config_binary(&self) -> PathBuf {
         self.config().join("binary")
     }
+
+    pub fn config_network(&self) -> PathBuf {
+        self.config().join("network")
+    }
+
+    pub fn config_link(&self) -> PathBuf {
+        self.config().join("link")
+    }
+
+    pub fn config_template(&self) -> PathBuf {
+        self.config().join("template")
+    }
+
+    pub fn config_secret(&self) -> PathBuf {
+        self.config().join("secret")
+    }
+
+    pub fn config_plugin(&self) -> PathBuf {
+        self.config().join("plugin")
+    }
 }
 
 #[cfg(test)]
 impl From<&tempfile::TempDir> for Prefix {
     fn from(value: &tempfile::TempDir) -> Self {
-        Self::new(Some(value.path().into()))
+        Self::new(Some(value.path().into())).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_home_relative() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let dot = prefix.dot().display().to_string();
+        assert_eq!(prefix.home_relative(&prefix.dot(), "$HOME"), dot);
+
+        let Some(home) = my_home().unwrap() else {
+            return;
+        };
+        let prefix = Prefix::new(Some(home)).unwrap();
+        assert_eq!(prefix.home_relative(&prefix.dot(), "$HOME"), "$HOME/.dot");
+        assert_eq!(prefix.home_relative(prefix.prefix(), "%d"), "%d");
+        assert_eq!(prefix.home_relative(Path::new("/opt/dot"), "%d"), "/opt/dot");
     }
 }
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::error::{Error, Result, WithPath};
+use crate::init::{Shell, block, migrate};
+use crate::link::relocate;
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::ssh::SshKey;
+use crate::status::outdated_ssh_configs;
+
+#[derive(Debug, Args)]
+pub struct PrefixArgs {
+    #[command(subcommand)]
+    pub command: PrefixCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PrefixCommand {
+    /// Move the dot environment to another prefix and rewrite the generated files.
+    Move(PrefixMoveArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct PrefixMoveArgs {
+    /// The new prefix, created if missing.
+    pub new: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct PrefixMoveReport {
+    from: PathBuf,
+    to: PathBuf,
+    moved: Vec<(PathBuf, PathBuf)>,
+    rc_files: Vec<PathBuf>,
+    ssh_configs: Vec<String>,
+    hooks: Vec<PathBuf>,
+}
+
+impl Report for PrefixMoveReport {
+    fn print(&self) {
+        for (from, to) in &self.moved {
+            println!("Moved {} to {}", from.display(), to.display());
+        }
+        for path in &self.rc_files {
+            println!("Regenerated {}", path.display());
+        }
+        for key in &self.ssh_configs {
+            println!("Regenerated ssh config {}", key);
+        }
+        for path in &self.hooks {
+            println!("Rewrote {}", path.display());
+        }
+        println!(
+            "Moved the dot environment from {} to {}, open a new shell to use it",
+            self.from.display(),
+            self.to.display()
+        );
+    }
+}
+
+/// Absolute path of the new prefix, created unless `--dry-run` is set.
+fn resolve(prefix: &Prefix, new: &Path) -> Result<PathBuf> {
+    let new = if new.is_absolute() {
+        new.to_owned()
+    } else {
+        std::env::current_dir().with_path(".")?.join(new)
+    };
+    if !new.exists() && !prefix.dry_run(format_args!("create directory {:?}", new)) {
+        std::fs::create_dir_all(&new).with_path(&new)?;
+    }
+    if new.exists() { new.canonicalize().with_path(&new) } else { Ok(new) }
+}
+
+/// Move the managed blocks of the rc files generated by `dot init`.
+fn move_rc(from: &Prefix, to: &Prefix, report: &mut PrefixMoveReport) -> Result<()> {
+    for shell in Shell::value_variants().iter().copied() {
+        let paths: Vec<_> = shell
+            .rc_paths(from)
+            .into_iter()
+            .filter(|path| {
+                std::fs::read_to_string(path).is_ok_and(|current| block::has_block(&current))
+            })
+            .collect();
+        if paths.is_empty() {
+            continue;
+        }
+        shell.generate_rc(to, None)?;
+        for path in paths {
+            block::remove(from, &path)?;
+        }
+        report.rc_files.extend(shell.rc_paths(to));
+    }
+    Ok(())
+}
+
+/// Replace the old prefix in the hooks, they are generated by the installed binaries which
+/// only know absolute paths.
+fn rewrite_hooks(from: &Prefix, to: &Prefix, report: &mut PrefixMoveReport) -> Result<()> {
+    let hook_dir = to.hook();
+    if !hook_dir.exists() {
+        return Ok(());
+    }
+    let (old, new) =
+        (format!("{}/", from.prefix().display()), format!("{}/", to.prefix().display()));
+    for entry in std::fs::read_dir(&hook_dir).with_path(&hook_dir)? {
+        let path = entry.with_path(&hook_dir)?.path();
+        let Ok(current) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        if !current.contains(&old) || to.dry_run(format_args!("rewrite {:?}", path)) {
+            continue;
+        }
+        log::info!(path:? = path; "Rewriting hook");
+        std::fs::write(&path, current.replace(&old, &new)).with_path(&path)?;
+        report.hooks.push(path);
+    }
+    report.hooks.sort();
+    Ok(())
+}
+
+fn move_prefix(prefix: &Prefix, args: &PrefixMoveArgs) -> Result<PrefixMoveReport> {
+    let new = resolve(prefix, &args.new)?;
+    if new == prefix.prefix() {
+        return Err(Error::Config(format!("{:?} is already the prefix", new)));
+    }
+    if [prefix.dot(), prefix.local(), prefix.state(), prefix.cache()]
+        .iter()
+        .any(|dir| new.starts_with(dir))
+    {
+        return Err(Error::Config(format!("{:?} is inside the dot environment", new)));
+    }
+    let to = prefix.to_root(new);
+    let mut report = PrefixMoveReport {
+        from: prefix.prefix().into(),
+        to: to.prefix().into(),
+        ..Default::default()
+    };
+
+    let included = SshKey::is_ssh_config_dir_included(prefix)?;
+    report.moved = migrate(prefix, &to)?;
+    // The link targets inside the prefix follow it, after the more precise moves.
+    let mut moves = report.moved.clone();
+    moves.push((prefix.prefix().into(), to.prefix().into()));
+    relocate(&to, &moves)?;
+    if included {
+        SshKey::exclude_ssh_config_dir(prefix)?;
+        SshKey::include_ssh_config_dir(&to)?;
+    }
+    for key in outdated_ssh_configs(&to)? {
+        key.generate_ssh_config(&to)?;
+        report.ssh_configs.push(key.key().to_owned());
+    }
+    move_rc(prefix, &to, &mut report)?;
+    rewrite_hooks(prefix, &to, &mut report)?;
+    Ok(report)
+}
+
+pub fn entry_prefix(prefix: &Prefix, args: PrefixArgs) -> Result<()> {
+    match args.command {
+        PrefixCommand::Move(args) => prefix.emit(&move_prefix(prefix, &args)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::link::{created_links, link};
+
+    #[test]
+    fn test_move_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("old")).unwrap();
+        let old = Prefix::new(Some(temp_dir.path().join("old"))).unwrap();
+        old.create_dir_all().unwrap();
+        Shell::Zsh.generate_rc(&old, None).unwrap();
+        SshKey::include_ssh_config_dir(&old).unwrap();
+        std::fs::write(old.local().join(".zsh_history"), "ls\n").unwrap();
+        let hook = old.hook().join("starship.zsh");
+        std::fs::write(&hook, format!("{}/starship init\n", old.bin().display())).unwrap();
+        std::fs::write(
+            old.config_link().join("link.toml"),
+            "[git]\nsource = \"config/git\"\ntarget = \"~/.config/git\"\n",
+        )
+        .unwrap();
+        link(&old, &[], false).unwrap();
+
+        let args = PrefixMoveArgs { new: temp_dir.path().join("new") };
+        let report = move_prefix(&old, &args).unwrap();
+        let new = old.to_root(temp_dir.path().join("new").canonicalize().unwrap());
+        assert!(report.moved.contains(&(old.dot(), new.dot())));
+        assert!(!old.dot().exists());
+        assert!(new.local().join(".zsh_history").exists());
+        let target = new.prefix().join(".config").join("git");
+        assert_eq!(created_links(&new).unwrap(), [target.clone()]);
+        assert_eq!(std::fs::read_link(&target).unwrap(), new.config_git());
+        assert!(std::fs::symlink_metadata(old.prefix().join(".config").join("git")).is_err());
+
+        assert!(!old.prefix().join(".zshenv").exists());
+        let zshenv = std::fs::read_to_string(new.prefix().join(".zshenv")).unwrap();
+        assert!(zshenv.contains(&format!("export DOTDIR={}\n", new.dot().display())));
+        assert_eq!(report.rc_files, [new.prefix().join(".zshenv")]);
+
+        assert!(!SshKey::is_ssh_config_dir_included(&old).unwrap());
+        assert!(SshKey::is_ssh_config_dir_included(&new).unwrap());
+        assert!(new.ssh_config().exists());
+
+        assert_eq!(report.hooks, [new.hook().join("starship.zsh")]);
+        assert_eq!(
+            std::fs::read_to_string(new.hook().join("starship.zsh")).unwrap(),
+            format!("{}/starship init\n", new.bin().display())
+        );
+
+        assert!(move_prefix(&new, &PrefixMoveArgs { new: new.dot() }).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use clap::{Args, CommandFactory};
+use semver::Version;
+use serde::Serialize;
+
+use crate::Cli;
+use crate::error::{Error, Result, WithPath, WithUrl, output};
+use crate::install::DOT_BINARY;
+use crate::network::NetworkConfig;
+use crate::output::Report;
+use crate::prefix::Prefix;
+
+const LATEST_RELEASE_URL: &str = "https://github.com/vnghia/dot/releases/latest";
+
+#[derive(Debug, Args)]
+#[command(disable_version_flag = true)]
+pub struct SelfUpdateArgs {
+    /// Version to update to. Default to the latest release.
+    #[arg(long)]
+    pub version: Option<String>,
+    /// Allow downgrading or reinstalling the current version.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelfUpdateReport {
+    current: String,
+    version: String,
+    path: PathBuf,
+    updated: bool,
+}
+
+impl Report for SelfUpdateReport {
+    fn print(&self) {
+        if self.updated {
+            println!("Updated dot from {} to {}", self.current, self.version);
+        }
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version> {
+    Version::parse(version.trim().trim_start_matches('v'))
+        .map_err(|e| Error::Config(format!("invalid version {}: {}", version.trim(), e)))
+}
+
+fn resolve_latest_version(prefix: &Prefix) -> Result<Version> {
+    let network = NetworkConfig::load(prefix)?;
+    let url = network.rewrite(LATEST_RELEASE_URL);
+    let response = network
+        .client()?
+        .get(&url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .with_url(&url)?;
+    // Github redirects the latest release to `/releases/tag/<tag>`.
+    log::debug!(url:% = response.url(); "Resolved latest release");
+    parse_version(
+        response.url().path_segments().and_then(|mut segments| segments.next_back()).unwrap_or(""),
+    )
+}
+
+/// Replace the dot binary of `Prefix::bin()` with the requested release.
+pub fn self_update(prefix: &Prefix, args: &SelfUpdateArgs) -> Result<SelfUpdateReport> {
+    let current_version = parse_version(env!("CARGO_PKG_VERSION"))?;
+    let version = match args.version.as_deref() {
+        Some(version) => parse_version(version)?,
+        None => {
+            log::info!(url:% = LATEST_RELEASE_URL; "Resolving latest release");
+            resolve_latest_version(prefix)?
+        }
+    };
+    log::info!(current:% = current_version, target:% = version; "Resolved dot version");
+    let bin_path = prefix.bin().join(DOT_BINARY.name);
+    let report = |updated| SelfUpdateReport {
+        current: current_version.to_string(),
+        version: version.to_string(),
+        path: bin_path.clone(),
+        updated,
+    };
+
+    if !args.force {
+        match version.cmp(&current_version) {
+            Ordering::Equal => {
+                log::info!("Already up to date");
+                return Ok(report(false));
+            }
+            // A development build is newer than the latest release, nothing to update.
+            Ordering::Less if args.version.is_none() => {
+                log::info!(latest:% = version; "Current version is newer than the latest release");
+                return Ok(report(false));
+            }
+            Ordering::Less => {
+                return Err(Cli::command()
+                    .error(
+                        clap::error::ErrorKind::InvalidValue,
+                        format!(
+                            "refusing to downgrade from {} to {} without --force",
+                            current_version, version
+                        ),
+                    )
+                    .into());
+            }
+            Ordering::Greater => {}
+        }
+    }
+
+    if prefix.dry_run(format_args!("download dot {} and replace {:?}", version, bin_path)) {
+        return Ok(report(false));
+    }
+    std::fs::create_dir_all(prefix.bin()).with_path(prefix.bin())?;
+    let bin_tmp_path = DOT_BINARY.stage(prefix, &version.to_string())?;
+
+    let staged_version = output(std::process::Command::new(&bin_tmp_path).arg("--version"))
+        .map(|stdout| String::from_utf8_lossy(&stdout).into_owned());
+    log::info!(version:? = staged_version; "Staged dot version");
+    if !staged_version.as_ref().is_ok_and(|staged_version| {
+        staged_version.split_whitespace().next_back().map(parse_version).is_some_and(
+            |staged_version| staged_version.is_ok_and(|staged_version| staged_version == version),
+        )
+    }) {
+        std::fs::remove_file(&bin_tmp_path).with_path(&bin_tmp_path)?;
+        return Err(Error::Config(format!(
+            "staged dot binary does not report version {}",
+            version
+        )));
+    }
+
+    // Renaming keeps the inode of the running binary alive so it can be replaced in place.
+    log::info!(from:? = bin_tmp_path, to:? = bin_path; "Replacing dot binary");
+    std::fs::rename(&bin_tmp_path, &bin_path).with_path(&bin_path)?;
+    Ok(report(true))
+}
+
+pub fn entry_self_update(prefix: &Prefix, args: SelfUpdateArgs) -> Result<()> {
+    prefix.emit(&self_update(prefix, &args)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::Command;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v0.8.8").unwrap(), Version::new(0, 8, 8));
+        assert_eq!(parse_version("0.8.8\n").unwrap(), Version::new(0, 8, 8));
+        assert!(matches!(parse_version("latest"), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_parse_self_update_args() {
+        let Command::SelfUpdate(args) =
+            Cli::try_parse_from(["dot", "self-update", "--version", "1.0.0", "--force"])
+                .unwrap()
+                .command
+        else {
+            unreachable!()
+        };
+        assert_eq!(args.version.as_deref(), Some("1.0.0"));
+        assert!(args.force);
+    }
+}
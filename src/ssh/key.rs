@@ -1,16 +1,21 @@
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
 use const_format::formatc;
 use convert_case::Casing;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::SshKeyArgs;
+use crate::error::{Error, Result, WithPath};
+use crate::output::Report;
 use crate::prefix::Prefix;
 use crate::utils::{load_predefined_and_local, unwrap_or_missing_argument};
 
+/// First line of the configs generated into `config.d`.
+const GENERATED_HEADER: &str = "# AUTO GENERATED FILE. DO NOT EDIT\n";
+
 const SSH_INCLUDE_CONDIG_DIR_LINE: &str = formatc!("Include {}/*", Prefix::SSH_CONFIG_DIR_NAME);
 
 #[derive(Deserialize, Debug)]
@@ -29,46 +34,111 @@ pub struct SshKey {
     config: SshConfig,
 }
 
+/// Generated ssh config of a key.
+#[derive(Debug, Serialize)]
+pub struct SshReport {
+    key: String,
+    hostname: String,
+    config: PathBuf,
+    identity_file: PathBuf,
+}
+
+impl Report for SshReport {}
+
 impl SshKey {
-    pub fn load_predefined_key(prefix: &Prefix) -> &'static HashMap<String, Self> {
+    pub fn load_predefined_key(prefix: &Prefix) -> Result<&'static HashMap<String, Self>> {
         static PREDEFINED_CONFIG: OnceLock<HashMap<String, SshKey>> = OnceLock::new();
-        PREDEFINED_CONFIG.get_or_init(|| {
+        PREDEFINED_CONFIG.get_or_try_init(|| {
             let configs: HashMap<String, SshConfig> =
-                load_predefined_and_local(prefix.config_ssh().join("key.toml"));
-            configs.into_iter().map(|(key, config)| (key.clone(), SshKey { key, config })).collect()
+                load_predefined_and_local(prefix.config_ssh().join("key.toml"))?;
+            Ok(configs
+                .into_iter()
+                .map(|(key, config)| (key.clone(), SshKey { key, config }))
+                .collect())
         })
     }
 
-    fn include_ssh_config_dir(prefix: &Prefix) {
-        let ssh_dir = prefix.ssh();
-        let ssh_config_path = ssh_dir.join("config");
-        let mut ssh_config = std::fs::OpenOptions::new()
-            .read(true)
-            .create(true)
-            .append(true)
-            .open(&ssh_config_path)
-            .unwrap();
-
-        let mut line = BufReader::new(&ssh_config).lines();
-        let missing_include = loop {
-            match line.next() {
-                Some(line) => {
-                    if line.unwrap().trim() == SSH_INCLUDE_CONDIG_DIR_LINE {
-                        break false;
-                    }
-                }
-                None => break true,
+    pub fn get_predefined_key(prefix: &Prefix, key: &str) -> Result<&'static Self> {
+        Self::load_predefined_key(prefix)?
+            .get(key)
+            .ok_or_else(|| Error::Config(format!("predefined ssh key {} does not exist", key)))
+    }
+
+    /// Configs of `config.d` generated by `dot ssh`, the ones written by hand are left out.
+    pub fn generated_ssh_configs(prefix: &Prefix) -> Result<Vec<PathBuf>> {
+        let ssh_config_dir = prefix.ssh_config();
+        if !ssh_config_dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(&ssh_config_dir).with_path(&ssh_config_dir)? {
+            let path = entry.with_path(&ssh_config_dir)?.path();
+            if std::fs::read_to_string(&path)
+                .is_ok_and(|content| content.starts_with(GENERATED_HEADER))
+            {
+                paths.push(path);
             }
-        };
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn is_ssh_config_dir_included(prefix: &Prefix) -> Result<bool> {
+        let ssh_config_path = prefix.ssh().join("config");
+        Ok(ssh_config_path.exists()
+            && std::fs::read_to_string(&ssh_config_path)
+                .with_path(&ssh_config_path)?
+                .lines()
+                .any(|line| line.trim() == SSH_INCLUDE_CONDIG_DIR_LINE))
+    }
 
-        if missing_include {
+    pub fn include_ssh_config_dir(prefix: &Prefix) -> Result<()> {
+        let ssh_config_path = prefix.ssh().join("config");
+        if !Self::is_ssh_config_dir_included(prefix)?
+            && !prefix.dry_run(format_args!(
+                "append `{}` to {:?}",
+                SSH_INCLUDE_CONDIG_DIR_LINE, ssh_config_path
+            ))
+        {
             log::info!(to:? = ssh_config_path; "Appending include config line");
-            ssh_config.write_all(formatc!("{}\n", SSH_INCLUDE_CONDIG_DIR_LINE).as_bytes()).unwrap();
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&ssh_config_path)
+                .and_then(|mut ssh_config| {
+                    ssh_config.write_all(formatc!("{}\n", SSH_INCLUDE_CONDIG_DIR_LINE).as_bytes())
+                })
+                .with_path(&ssh_config_path)?;
+        }
+        if prefix.ssh_config().exists()
+            || prefix.dry_run(format_args!("create directory {:?}", prefix.ssh_config()))
+        {
+            return Ok(());
+        }
+        std::fs::create_dir_all(prefix.ssh_config()).with_path(prefix.ssh_config())
+    }
+
+    /// Remove the line added by `include_ssh_config_dir` from `~/.ssh/config`.
+    pub fn exclude_ssh_config_dir(prefix: &Prefix) -> Result<()> {
+        let ssh_config_path = prefix.ssh().join("config");
+        if !Self::is_ssh_config_dir_included(prefix)?
+            || prefix.dry_run(format_args!(
+                "remove `{}` from {:?}",
+                SSH_INCLUDE_CONDIG_DIR_LINE, ssh_config_path
+            ))
+        {
+            return Ok(());
         }
-        std::fs::create_dir_all(prefix.ssh_config()).unwrap();
+        log::info!(from:? = ssh_config_path; "Removing include config line");
+        let content = std::fs::read_to_string(&ssh_config_path).with_path(&ssh_config_path)?;
+        let content: String = content
+            .split_inclusive('\n')
+            .filter(|line| line.trim() != SSH_INCLUDE_CONDIG_DIR_LINE)
+            .collect();
+        std::fs::write(&ssh_config_path, content).with_path(&ssh_config_path)
     }
 
-    fn generate_key(&self, prefix: &Prefix) {
+    fn generate_key(&self, prefix: &Prefix) -> Result<()> {
         let config = &self.config;
         let skm_bin = prefix.bin().join("skm");
         let mut command = std::process::Command::new(skm_bin);
@@ -82,35 +152,39 @@ impl SshKey {
             .arg("-t")
             .arg("ed25519");
         log::info!(command:? = command; "Generating new ssh key");
-        command.spawn().unwrap().wait().unwrap().exit_ok().unwrap();
+        if prefix.dry_run(format_args!("run {:?}", command)) {
+            return Ok(());
+        }
+        prefix.run(&mut command)
     }
 
-    fn check_key(&self, prefix: &Prefix) -> PathBuf {
+    fn check_key(&self, prefix: &Prefix) -> Result<PathBuf> {
         let key_dir = prefix.skm().join(&self.key);
         let public_path = key_dir.join("id_ed25519.pub");
         let private_path = key_dir.join("id_ed25519");
+        // The key is not generated during a dry run.
+        if prefix.is_dry_run() {
+            return Ok(private_path);
+        }
         if !public_path.exists() {
-            panic!("public key shoud exist at {:?}", &public_path);
+            return Err(Error::Config(format!("public key should exist at {:?}", &public_path)));
         }
         if !private_path.exists() {
-            panic!("private key should exist at {:?}", &private_path);
+            return Err(Error::Config(format!("private key should exist at {:?}", &private_path)));
         }
         log::trace!(public:? = public_path, private:? = private_path; "Using key");
-        private_path
+        Ok(private_path)
     }
 
-    fn generate_ssh_config(&self, prefix: &Prefix) {
+    fn ssh_config_content(&self, prefix: &Prefix, key_path: &Path) -> String {
         let config = &self.config;
-        let ssh_config_path = prefix.ssh_config().join(&self.key);
-        let key_path = self.check_key(prefix);
-
-        let mut ssh_content = "# AUTO GENERATED FILE. DO NOT EDIT\n\n".to_string();
+        let mut ssh_content = format!("{}\n", GENERATED_HEADER);
         ssh_content += &format!("Host {}\n", &self.key);
         ssh_content += &format!("\tHostname {}\n", &config.hostname);
         ssh_content += "\tAddKeysToAgent yes\n";
         ssh_content += "\tIdentitiesOnly yes\n";
-        ssh_content +=
-            &format!("\tIdentityFile {}\n", key_path.into_os_string().into_string().unwrap());
+        // `%d` is the home directory of the user for ssh.
+        ssh_content += &format!("\tIdentityFile {}\n", prefix.home_relative(key_path, "%d"));
 
         for (k, v) in config.additions.iter() {
             ssh_content += &format!("\t{} {}\n", k.to_case(convert_case::Case::Pascal), v);
@@ -120,24 +194,44 @@ impl SshKey {
         {
             ssh_content += "\tUseKeychain yes\n";
         }
+        ssh_content
+    }
+
+    pub fn generate_ssh_config(&self, prefix: &Prefix) -> Result<SshReport> {
+        let config = &self.config;
+        let ssh_config_path = prefix.ssh_config().join(&self.key);
+        let key_path = self.check_key(prefix)?;
+        let ssh_content = self.ssh_config_content(prefix, &key_path);
 
         log::info!(path:? = ssh_config_path; "Generating ssh config");
         log::trace!(content:% = ssh_content; "Generating ssh config");
 
-        std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(ssh_config_path)
-            .unwrap()
-            .write_all(ssh_content.as_bytes())
-            .unwrap();
+        if !prefix.dry_run(format_args!("write {:?}:\n{}", ssh_config_path, ssh_content)) {
+            std::fs::write(&ssh_config_path, ssh_content).with_path(&ssh_config_path)?;
+        }
+        Ok(SshReport {
+            key: self.key.clone(),
+            hostname: config.hostname.clone(),
+            config: ssh_config_path,
+            identity_file: key_path,
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
     }
 
-    pub fn generate(&self, prefix: &Prefix) {
-        Self::include_ssh_config_dir(prefix);
-        self.generate_key(prefix);
-        self.generate_ssh_config(prefix);
+    /// Whether the generated ssh config of this key matches its current config.
+    pub fn is_ssh_config_up_to_date(&self, prefix: &Prefix) -> Result<bool> {
+        let ssh_config_path = prefix.ssh_config().join(&self.key);
+        let current = std::fs::read_to_string(&ssh_config_path).with_path(&ssh_config_path)?;
+        Ok(current == self.ssh_config_content(prefix, &self.check_key(prefix)?))
+    }
+
+    pub fn generate(&self, prefix: &Prefix) -> Result<SshReport> {
+        Self::include_ssh_config_dir(prefix)?;
+        self.generate_key(prefix)?;
+        self.generate_ssh_config(prefix)
     }
 }
 
@@ -191,7 +285,7 @@ mod test {
                     additions: [("snake_case".to_owned(), "yes".to_owned())].into_iter().collect(),
                 },
             };
-            ssh_key.generate_ssh_config(prefix);
+            ssh_key.generate_ssh_config(prefix).unwrap();
             ssh_key
         }
     }
@@ -207,23 +301,42 @@ mod tests {
     fn test_include_ssh_config_dir_non_existent() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         let config_path = prefix.ssh().join("config");
-        SshKey::include_ssh_config_dir(&prefix);
+        SshKey::include_ssh_config_dir(&prefix).unwrap();
         assert_eq!(
             std::fs::read_to_string(config_path).unwrap(),
             formatc!("{}\n", SSH_INCLUDE_CONDIG_DIR_LINE)
         );
     }
 
+    #[test]
+    fn test_generate_dry_run() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix = Prefix::from(&temp_dir).with_dry_run(true);
+        SshKey {
+            key: "key".into(),
+            config: SshConfig {
+                hostname: "host".into(),
+                comment: None,
+                additions: Default::default(),
+            },
+        }
+        .generate(&prefix)
+        .unwrap();
+        assert!(!prefix.ssh().join("config").exists());
+        assert!(!prefix.ssh_config().exists());
+        assert!(!prefix.skm().exists());
+    }
+
     #[test]
     fn test_include_ssh_config_dir_missing() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         let config_path = prefix.ssh().join("config");
         std::fs::write(&config_path, "test content\ntest config\n").unwrap();
-        SshKey::include_ssh_config_dir(&prefix);
+        SshKey::include_ssh_config_dir(&prefix).unwrap();
         assert_eq!(
             std::fs::read_to_string(&config_path).unwrap(),
             formatc!("test content\ntest config\n{}\n", SSH_INCLUDE_CONDIG_DIR_LINE)
@@ -234,14 +347,14 @@ mod tests {
     fn test_include_ssh_config_dir_non_missing() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         let config_path = prefix.ssh().join("config");
         std::fs::write(
             &config_path,
             formatc!("test content\ntest config\n{}\n", SSH_INCLUDE_CONDIG_DIR_LINE),
         )
         .unwrap();
-        SshKey::include_ssh_config_dir(&prefix);
+        SshKey::include_ssh_config_dir(&prefix).unwrap();
         assert_eq!(
             std::fs::read_to_string(&config_path).unwrap(),
             formatc!("test content\ntest config\n{}\n", SSH_INCLUDE_CONDIG_DIR_LINE)
@@ -252,7 +365,7 @@ mod tests {
     fn test_generate_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         let config_path = prefix.ssh_config().join("key");
 
         SshKey::fake(&prefix, "key".into(), "host".into());
@@ -274,7 +387,7 @@ mod tests {
     fn test_parse_predefined_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         std::fs::write(
             prefix.config_ssh().join("key.toml"),
             r#"
@@ -288,7 +401,7 @@ key = "value"
 "#,
         )
         .unwrap();
-        let profiles = SshKey::load_predefined_key(&prefix);
+        let profiles = SshKey::load_predefined_key(&prefix).unwrap();
         assert_eq!(profiles.get("text").unwrap(), &SshKey {
             key: "text".into(),
             config: SshConfig {
@@ -311,7 +424,7 @@ key = "value"
     fn test_parse_predefined_and_local_config() {
         let temp_dir = TempDir::new().unwrap();
         let prefix: Prefix = (&temp_dir).into();
-        prefix.create_dir_all();
+        prefix.create_dir_all().unwrap();
         std::fs::write(
             prefix.config_ssh().join("key.toml"),
             r#"
@@ -330,7 +443,7 @@ key = "value"
 "#,
         )
         .unwrap();
-        let profiles = SshKey::load_predefined_key(&prefix);
+        let profiles = SshKey::load_predefined_key(&prefix).unwrap();
         assert_eq!(profiles.get("text").unwrap(), &SshKey {
             key: "text".into(),
             config: SshConfig {
@@ -2,6 +2,8 @@ mod key;
 use clap::Args;
 
 pub use self::key::SshKey;
+use crate::config::DotConfig;
+use crate::error::Result;
 use crate::prefix::Prefix;
 use crate::utils::parse_addition;
 
@@ -30,24 +32,21 @@ pub struct SshKeyArgs {
     addition: Vec<(String, String)>,
 }
 
-pub fn get_default_key() -> Option<String> {
-    std::env::var("DOT_SSH_DEFAULT_KEY").ok()
-}
-
-pub fn entry_ssh(prefix: &Prefix, args: SshArgs) {
-    if let Some(config) = args.config {
-        SshKey::load_predefined_key(prefix).get(&config).unwrap().generate(prefix)
+pub fn entry_ssh(prefix: &Prefix, args: SshArgs) -> Result<()> {
+    let report = if let Some(config) = args.config {
+        SshKey::get_predefined_key(prefix, &config)?.generate(prefix)
     } else {
         match SshKey::try_from(args.key) {
             Ok(key) => key.generate(prefix),
             Err(e) => {
-                if let Some(config) = get_default_key() {
+                if let Some(config) = DotConfig::load(prefix)?.default_key() {
                     log::info!(config:% = config; "Use config from environment variable");
-                    SshKey::load_predefined_key(prefix).get(&config).unwrap().generate(prefix)
+                    SshKey::get_predefined_key(prefix, &config)?.generate(prefix)
                 } else {
-                    e.exit()
+                    Err(e.into())
                 }
             }
         }
-    }
+    }?;
+    prefix.emit(&report)
 }
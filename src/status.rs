@@ -0,0 +1,351 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use git2::{Repository, Status, StatusOptions, SubmoduleIgnore, SubmoduleStatus};
+use serde::Serialize;
+
+use crate::error::{Result, WithContext, WithPath};
+use crate::git::fetch;
+use crate::init::{Shell, block};
+use crate::install::InstallConfig;
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::ssh::SshKey;
+
+#[derive(Debug, Args)]
+pub struct StatusArgs {
+    /// Fetch `origin` before counting the commits ahead and behind.
+    #[arg(long)]
+    pub fetch: bool,
+    /// The corresponding shell of the dotfile environments.
+    #[arg(short, long, value_enum, default_value_t = Shell::Zsh)]
+    pub shell: Shell,
+    /// Custom .bashrc/.zshenv/dot.fish in case of read-only default rc file.
+    #[arg(long)]
+    pub rc_file: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Change {
+    /// Two letters status of `git status --short`.
+    status: String,
+    path: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SubmoduleState {
+    Clean,
+    Uninitialized,
+    /// Checked out at another commit than the one recorded by the dot repository.
+    Moved,
+    /// Contains uncommitted changes.
+    Dirty,
+}
+
+#[derive(Debug, Serialize)]
+struct Submodule {
+    name: String,
+    state: SubmoduleState,
+}
+
+#[derive(Debug, Serialize)]
+struct RepoStatus {
+    /// Checked out branch, `None` if `HEAD` is detached.
+    branch: Option<String>,
+    head: String,
+    upstream: Option<String>,
+    ahead: usize,
+    behind: usize,
+    changes: Vec<Change>,
+    submodules: Vec<Submodule>,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+struct Drift {
+    kind: &'static str,
+    name: String,
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    repo: Option<RepoStatus>,
+    drifts: Vec<Drift>,
+}
+
+impl Report for StatusReport {
+    fn print(&self) {
+        match &self.repo {
+            Some(repo) => {
+                match &repo.branch {
+                    Some(branch) => println!("On branch {}", branch),
+                    None => println!("HEAD detached at {:.7}", repo.head),
+                }
+                if let Some(upstream) = &repo.upstream {
+                    match (repo.ahead, repo.behind) {
+                        (0, 0) => println!("Up to date with {}", upstream),
+                        (ahead, behind) => {
+                            println!("{} ahead, {} behind {}", ahead, behind, upstream)
+                        }
+                    }
+                }
+                if !repo.changes.is_empty() {
+                    println!("\nUncommitted changes:");
+                    for change in &repo.changes {
+                        println!("  {} {}", change.status, change.path);
+                    }
+                }
+                let submodules: Vec<_> = repo
+                    .submodules
+                    .iter()
+                    .filter(|submodule| submodule.state != SubmoduleState::Clean)
+                    .collect();
+                if !submodules.is_empty() {
+                    println!("\nSubmodules:");
+                    for submodule in submodules {
+                        let state = match submodule.state {
+                            SubmoduleState::Clean => "clean",
+                            SubmoduleState::Uninitialized => "uninitialized",
+                            SubmoduleState::Moved => "new commits",
+                            SubmoduleState::Dirty => "modified content",
+                        };
+                        println!("  {} ({})", submodule.name, state);
+                    }
+                }
+            }
+            None => println!("The dot directory is not a git repository"),
+        }
+        if self.drifts.is_empty() {
+            println!("\nThe environment matches the dot repository");
+        } else {
+            println!("\nDrift:");
+            for drift in &self.drifts {
+                println!("  {} {}: {}", drift.kind, drift.name, drift.detail);
+            }
+        }
+    }
+}
+
+fn short_status(status: Status) -> String {
+    if status.is_wt_new() {
+        return "??".to_owned();
+    }
+    let index = if status.is_index_new() {
+        'A'
+    } else if status.is_index_modified() || status.is_index_typechange() {
+        'M'
+    } else if status.is_index_deleted() {
+        'D'
+    } else if status.is_index_renamed() {
+        'R'
+    } else {
+        ' '
+    };
+    let worktree = if status.is_wt_modified() || status.is_wt_typechange() {
+        'M'
+    } else if status.is_wt_deleted() {
+        'D'
+    } else if status.is_wt_renamed() {
+        'R'
+    } else {
+        ' '
+    };
+    format!("{}{}", index, worktree)
+}
+
+fn submodule_state(status: SubmoduleStatus) -> SubmoduleState {
+    if status.is_wd_uninitialized() {
+        SubmoduleState::Uninitialized
+    } else if status.is_wd_modified() {
+        SubmoduleState::Moved
+    } else if status.contains(SubmoduleStatus::WD_INDEX_MODIFIED)
+        || status.is_wd_wd_modified()
+        || status.is_wd_untracked()
+    {
+        SubmoduleState::Dirty
+    } else {
+        SubmoduleState::Clean
+    }
+}
+
+fn repo_status(prefix: &Prefix, repo: &Repository, fetch_origin: bool) -> Result<RepoStatus> {
+    let head = repo.head().with_context("can not resolve HEAD")?;
+    let branch = head.is_branch().then(|| head.shorthand().unwrap_or_default().to_owned());
+    let head = head.peel_to_commit().with_context("can not resolve HEAD")?.id();
+
+    let (mut upstream, mut ahead, mut behind) = (None, 0, 0);
+    // A detached `HEAD` is pinned with `dot init --ref` on purpose, it follows no branch.
+    if let Some(branch) = &branch {
+        let upstream_commit = if fetch_origin {
+            Some(fetch(prefix, repo, "origin", branch)?.id())
+        } else {
+            repo.refname_to_id(&format!("refs/remotes/origin/{}", branch)).ok()
+        };
+        if let Some(upstream_commit) = upstream_commit {
+            (ahead, behind) = repo
+                .graph_ahead_behind(head, upstream_commit)
+                .with_context("can not compare HEAD with origin")?;
+            upstream = Some(format!("origin/{}", branch));
+        }
+    }
+
+    let changes = repo
+        .statuses(Some(
+            StatusOptions::new()
+                .include_untracked(true)
+                .include_ignored(false)
+                .exclude_submodules(true),
+        ))
+        .with_context("can not read the dot repository status")?
+        .iter()
+        .map(|entry| Change {
+            status: short_status(entry.status()),
+            path: entry.path().unwrap_or_default().to_owned(),
+        })
+        .collect();
+
+    let mut submodules = vec![];
+    for submodule in repo.submodules().with_context("can not list submodules")? {
+        let name = submodule.name().unwrap_or_default().to_owned();
+        let status = repo
+            .submodule_status(&name, SubmoduleIgnore::None)
+            .with_context(format!("can not read submodule {} status", name))?;
+        submodules.push(Submodule { name, state: submodule_state(status) });
+    }
+    submodules.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(RepoStatus { branch, head: head.to_string(), upstream, ahead, behind, changes, submodules })
+}
+
+/// Installed binaries whose version differs from `version.toml`.
+fn binary_drifts(prefix: &Prefix) -> Result<Vec<Drift>> {
+    if !prefix.config_binary().join("version.toml").exists() {
+        return Ok(vec![]);
+    }
+    let versions = InstallConfig::load_version(prefix)?;
+    let mut drifts = vec![];
+    for config in InstallConfig::value_variants().iter().copied() {
+        let Some(version) = versions.get(&config) else {
+            continue;
+        };
+        if !prefix.bin().join(config.name()).exists() {
+            continue;
+        }
+        let detail = match config.installed_version(prefix) {
+            Some(stdout) if InstallConfig::reports_version(&stdout, version) => continue,
+            Some(stdout) => format!(
+                "expected {} but got {}",
+                version,
+                stdout.lines().next().unwrap_or_default()
+            ),
+            None => format!("expected {} but it does not run", version),
+        };
+        drifts.push(Drift { kind: "binary", name: config.name().to_owned(), detail });
+    }
+    Ok(drifts)
+}
+
+/// Generated ssh configs of `config.d` differing from the predefined key of the same name.
+pub fn outdated_ssh_configs(prefix: &Prefix) -> Result<Vec<&'static SshKey>> {
+    let ssh_config_dir = prefix.ssh_config();
+    if !ssh_config_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in std::fs::read_dir(&ssh_config_dir).with_path(&ssh_config_dir)? {
+        names.push(entry.with_path(&ssh_config_dir)?.file_name().to_string_lossy().into_owned());
+    }
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    names.sort();
+    let keys = SshKey::load_predefined_key(prefix)?;
+    let mut outdated = vec![];
+    for name in names {
+        // A missing key can not be up to date either.
+        if let Some(key) = keys.get(&name)
+            && !key.is_ssh_config_up_to_date(prefix).unwrap_or(false)
+        {
+            outdated.push(key);
+        }
+    }
+    Ok(outdated)
+}
+
+/// The rc file of `shell` if its managed block differs from the generated content.
+pub fn outdated_rc(
+    prefix: &Prefix,
+    shell: Shell,
+    rc_file: Option<&str>,
+) -> Result<Option<PathBuf>> {
+    let path = shell.rc_path(prefix, rc_file);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let current = std::fs::read_to_string(&path).with_path(&path)?;
+    Ok((block::has_block(&current) && !shell.is_rc_up_to_date(prefix, &current)).then_some(path))
+}
+
+fn status(prefix: &Prefix, args: &StatusArgs) -> Result<StatusReport> {
+    let repo = match Repository::open(prefix.dot()) {
+        Ok(repo) => Some(repo_status(prefix, &repo, args.fetch)?),
+        Err(_) => None,
+    };
+
+    let mut drifts = binary_drifts(prefix)?;
+    drifts.extend(outdated_ssh_configs(prefix)?.into_iter().map(|key| Drift {
+        kind: "ssh config",
+        name: key.key().to_owned(),
+        detail: "differs from key.toml".to_owned(),
+    }));
+    if let Some(path) = outdated_rc(prefix, args.shell, args.rc_file.as_deref())? {
+        drifts.push(Drift {
+            kind: "rc file",
+            name: path.display().to_string(),
+            detail: "differs from the generated content".to_owned(),
+        });
+    }
+    Ok(StatusReport { repo, drifts })
+}
+
+pub fn entry_status(prefix: &Prefix, args: StatusArgs) -> Result<()> {
+    prefix.emit(&status(prefix, &args)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::utils::commit;
+
+    #[test]
+    fn test_status() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        let repo = Repository::init(prefix.dot()).unwrap();
+        commit(&repo, "README.md", "dot");
+        std::fs::write(prefix.dot().join("README.md"), "dot status").unwrap();
+
+        let zshenv = prefix.prefix().join(".zshenv");
+        block::write(&prefix, &zshenv, "export DOTDIR=/old\n").unwrap();
+        let args = StatusArgs { fetch: false, shell: Shell::Zsh, rc_file: None };
+        let report = status(&prefix, &args).unwrap();
+        let repo = report.repo.unwrap();
+        assert!(repo.branch.is_some());
+        assert!(repo.upstream.is_none());
+        assert_eq!(repo.changes.len(), 1);
+        assert_eq!(repo.changes[0].status, " M");
+        assert_eq!(report.drifts, [Drift {
+            kind: "rc file",
+            name: zshenv.display().to_string(),
+            detail: "differs from the generated content".to_owned(),
+        }]);
+
+        Shell::Zsh.generate_rc(&prefix, None).unwrap();
+        assert!(status(&prefix, &args).unwrap().drifts.is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Args;
+use serde::Serialize;
+
+use crate::constant::target::{TARGET_TRIPLET, arch, os};
+use crate::error::{Result, WithPath, output};
+use crate::output::Report;
+use crate::prefix::Prefix;
+use crate::utils::{find_files, load_predefined_and_local};
+
+/// Extension of the files rendered next to themselves without it.
+const TEMPLATE_EXTENSION: &str = "tmpl";
+/// Host table of `host.toml` applied to every host.
+const DEFAULT_HOST: &str = "default";
+/// Unchanged lines printed around a change by `dot diff`.
+const DIFF_CONTEXT: usize = 2;
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Render with the variables of another host.
+    #[arg(long)]
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FileDiff {
+    path: PathBuf,
+    diff: String,
+}
+
+/// Rendered files which differ from their destination.
+#[derive(Debug, Serialize)]
+struct DiffReport {
+    files: Vec<FileDiff>,
+}
+
+impl Report for DiffReport {
+    fn print(&self) {
+        for file in &self.files {
+            println!("--- {}\n+++ {} (rendered)", file.path.display(), file.path.display());
+            print!("{}", file.diff);
+        }
+    }
+}
+
+fn hostname() -> Result<String> {
+    let stdout = output(std::process::Command::new("uname").arg("-n"))?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_owned())
+}
+
+/// Variables replaced as `%name%`: `hostname`, `os`, `arch`, `target` and the entries of the
+/// `default` and `<hostname>` tables of `config/template/host.toml`.
+fn variables(prefix: &Prefix, hostname: String) -> Result<HashMap<String, String>> {
+    let mut variables: HashMap<String, String> = [
+        ("os", os::FULL.to_owned()),
+        ("arch", arch::FULL.to_owned()),
+        ("target", TARGET_TRIPLET.to_owned()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect();
+
+    let path = prefix.config_template().join("host.toml");
+    if path.exists() {
+        let mut hosts: HashMap<String, toml::Table> = load_predefined_and_local(path)?;
+        for host in [DEFAULT_HOST, &hostname] {
+            for (k, v) in hosts.remove(host).unwrap_or_default() {
+                let v = match v {
+                    toml::Value::String(v) => v,
+                    v => v.to_string(),
+                };
+                variables.insert(k, v);
+            }
+        }
+    }
+    log::debug!(hostname:% = hostname; "Template variables");
+    variables.insert("hostname".into(), hostname);
+    Ok(variables)
+}
+
+/// Replace the `%name%` tokens of known variables in a single left to right pass, so that the
+/// substituted values are never substituted again. Unknown tokens are kept as is.
+fn render(content: &str, variables: &HashMap<String, String>) -> String {
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find('%') {
+        rendered += &rest[..start];
+        let after = &rest[start + 1..];
+        match after.find('%').and_then(|end| Some((end, variables.get(&after[..end])?))) {
+            Some((end, value)) => {
+                rendered += value;
+                rest = &after[end + 1..];
+            }
+            None => {
+                // The closing `%` may open the next token.
+                rendered.push('%');
+                rest = after;
+            }
+        }
+    }
+    rendered + rest
+}
+
+/// Every template of the dot repository with its destination and rendered content.
+fn rendered(prefix: &Prefix, hostname: String) -> Result<Vec<(PathBuf, String)>> {
+    let dot_dir = prefix.dot();
+    if !dot_dir.exists() {
+        return Ok(vec![]);
+    }
+    let variables = variables(prefix, hostname)?;
+    find_files(&dot_dir, TEMPLATE_EXTENSION)?
+        .into_iter()
+        .map(|template| {
+            let content = std::fs::read_to_string(&template).with_path(&template)?;
+            Ok((template.with_extension(""), render(&content, &variables)))
+        })
+        .collect()
+}
+
+/// Render the templates of the dot repository, return the destinations which changed.
+pub fn render_all(prefix: &Prefix) -> Result<Vec<PathBuf>> {
+    let mut changed = vec![];
+    for (path, content) in rendered(prefix, hostname()?)? {
+        if std::fs::read_to_string(&path).is_ok_and(|current| current == content) {
+            continue;
+        }
+        if !prefix.dry_run(format_args!("render {:?}", path)) {
+            log::info!(path:? = path; "Rendering template");
+            std::fs::write(&path, content).with_path(&path)?;
+        }
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+/// A line based diff showing the changed lines with `DIFF_CONTEXT` lines around them.
+fn diff(old: &str, new: &str) -> String {
+    let (old, new): (Vec<_>, Vec<_>) = (old.lines().collect(), new.lines().collect());
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+            lines.push(('+', new[j]));
+            j += 1;
+        } else {
+            lines.push(('-', old[i]));
+            i += 1;
+        }
+    }
+
+    let mut shown = vec![false; lines.len()];
+    for (k, _) in lines.iter().enumerate().filter(|(_, (tag, _))| *tag != ' ') {
+        let end = (k + DIFF_CONTEXT + 1).min(lines.len());
+        shown[k.saturating_sub(DIFF_CONTEXT)..end].fill(true);
+    }
+    let mut result = String::new();
+    let mut last = None;
+    for (k, (tag, line)) in lines.into_iter().enumerate().filter(|(k, _)| shown[*k]) {
+        if last.is_some_and(|last| last + 1 != k) {
+            result += "@@\n";
+        }
+        result += &format!("{}{}\n", tag, line);
+        last = Some(k);
+    }
+    result
+}
+
+pub fn entry_diff(prefix: &Prefix, args: DiffArgs) -> Result<()> {
+    let hostname = match args.hostname {
+        Some(hostname) => hostname,
+        None => hostname()?,
+    };
+    let mut files = vec![];
+    for (path, content) in rendered(prefix, hostname)? {
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        if current != content {
+            files.push(FileDiff { diff: diff(&current, &content), path });
+        }
+    }
+    prefix.emit(&DiffReport { files })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let variables: HashMap<_, _> = [("a", "%b%"), ("b", "2")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        assert_eq!(render("%a% %b% 100% %b%", &variables), "%b% 2 100% 2");
+        assert_eq!(render("50%%b%%", &variables), "50%2%");
+    }
+
+    #[test]
+    fn test_render_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        prefix.create_dir_all().unwrap();
+        std::fs::write(
+            prefix.config_template().join("host.toml"),
+            "[default]\nsize = 12\n\n[laptop]\nsize = 16\n",
+        )
+        .unwrap();
+        let app_dir = prefix.dot().join("config").join("app");
+        std::fs::create_dir_all(&app_dir).unwrap();
+        std::fs::write(app_dir.join("app.toml.tmpl"), "os = \"%os%\"\nsize = %size%\n").unwrap();
+        std::fs::create_dir_all(prefix.local()).unwrap();
+        std::fs::write(prefix.local().join("skipped.tmpl"), "").unwrap();
+
+        let rendered = rendered(&prefix, "laptop".into()).unwrap();
+        assert_eq!(rendered, [(
+            app_dir.join("app.toml"),
+            format!("os = \"{}\"\nsize = 16\n", os::FULL)
+        )]);
+
+        assert_eq!(render_all(&prefix).unwrap(), [app_dir.join("app.toml")]);
+        assert!(
+            std::fs::read_to_string(app_dir.join("app.toml")).unwrap().ends_with("size = 12\n")
+        );
+        assert!(render_all(&prefix).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\n";
+        assert_eq!(diff(old, new), " a\n-b\n+B\n c\n d\n@@\n g\n h\n+i\n");
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use git2::{Oid, Repository};
+use serde::Serialize;
+
+use crate::error::{Result, WithContext};
+use crate::git::{checkout_ref, pull};
+use crate::init::{Shell, remembered_ref};
+use crate::install::InstallConfig;
+use crate::output::Report;
+use crate::plugin;
+use crate::prefix::Prefix;
+use crate::self_update::{SelfUpdateArgs, SelfUpdateReport, self_update};
+use crate::status::{outdated_rc, outdated_ssh_configs};
+
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    /// The corresponding shell of the dotfile environments.
+    #[arg(short, long, value_enum, default_value_t = Shell::Zsh)]
+    pub shell: Shell,
+    /// Custom .bashrc/.zshenv/dot.fish in case of read-only default rc file.
+    #[arg(long)]
+    pub rc_file: Option<String>,
+    /// Keep the current dot binary instead of updating it to the latest release.
+    #[arg(long)]
+    pub no_self_update: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdatedBinary {
+    name: &'static str,
+    from: Option<String>,
+    to: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct UpdateReport {
+    /// `HEAD` of the dot repository before and after pulling, if it moved.
+    repo: Option<(String, String)>,
+    submodules: Vec<String>,
+    binaries: Vec<UpdatedBinary>,
+    rc_files: Vec<PathBuf>,
+    ssh_configs: Vec<String>,
+    /// Plugins newly added to `plugins.toml`.
+    new_plugins: Vec<String>,
+    plugins: Vec<String>,
+    dot: Option<SelfUpdateReport>,
+}
+
+impl Report for UpdateReport {
+    fn print(&self) {
+        if let Some((from, to)) = &self.repo {
+            println!("Updated dot repository from {:.7} to {:.7}", from, to);
+        }
+        for name in &self.submodules {
+            println!("Updated submodule {}", name);
+        }
+        for binary in &self.binaries {
+            match &binary.from {
+                Some(from) => {
+                    println!("Reinstalled {} from {} to {}", binary.name, from, binary.to)
+                }
+                None => println!("Reinstalled {} {}", binary.name, binary.to),
+            }
+        }
+        for path in &self.rc_files {
+            println!("Regenerated {}", path.display());
+        }
+        for key in &self.ssh_configs {
+            println!("Regenerated ssh config {}", key);
+        }
+        for name in &self.new_plugins {
+            println!("Installed plugin {}", name);
+        }
+        for name in &self.plugins {
+            println!("Updated plugin {}", name);
+        }
+        if let Some(dot) = &self.dot {
+            dot.print();
+        }
+    }
+}
+
+fn submodule_ids(repo: &Repository) -> Result<HashMap<String, Option<Oid>>> {
+    Ok(repo
+        .submodules()
+        .with_context("can not list submodules")?
+        .iter()
+        .map(|subm| (subm.name().unwrap_or_default().to_owned(), subm.workdir_id()))
+        .collect())
+}
+
+fn head_id(repo: &Repository) -> Result<Oid> {
+    repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .with_context("can not resolve HEAD")
+        .map(|commit| commit.id())
+}
+
+fn load_version(prefix: &Prefix) -> Result<HashMap<InstallConfig, String>> {
+    if prefix.config_binary().join("version.toml").exists() {
+        InstallConfig::load_version(prefix)
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+/// Pull the dot repository, following the ref remembered by `dot init --ref` if any.
+fn update_repo(prefix: &Prefix, report: &mut UpdateReport) -> Result<()> {
+    let dot_dir = prefix.dot();
+    let Ok(repo) = Repository::open(&dot_dir) else {
+        log::warn!(dot:? = dot_dir; "Dot directory is not a git repository, skipping pull");
+        return Ok(());
+    };
+
+    let (head, submodules) = (head_id(&repo)?, submodule_ids(&repo)?);
+    match remembered_ref(&repo) {
+        Some(git_ref) => checkout_ref(prefix, &repo, &git_ref, true)?,
+        None => pull(prefix, &repo, None, None, true)?,
+    }
+
+    let new_head = head_id(&repo)?;
+    if new_head != head {
+        report.repo = Some((head.to_string(), new_head.to_string()));
+    }
+    let mut changed: Vec<_> = submodule_ids(&repo)?
+        .into_iter()
+        .filter(|(name, id)| submodules.get(name) != Some(id))
+        .map(|(name, _)| name)
+        .collect();
+    changed.sort();
+    report.submodules = changed;
+    Ok(())
+}
+
+/// Reinstall the installed binaries whose pinned version changed.
+fn update_binaries(
+    prefix: &Prefix,
+    versions: &HashMap<InstallConfig, String>,
+    report: &mut UpdateReport,
+) -> Result<()> {
+    let new_versions = load_version(prefix)?;
+    for config in InstallConfig::value_variants().iter().copied() {
+        // The dot binary is replaced by the self update.
+        if config == InstallConfig::Dot || !prefix.bin().join(config.name()).exists() {
+            continue;
+        }
+        let (from, Some(to)) = (versions.get(&config), new_versions.get(&config)) else {
+            continue;
+        };
+        if from == Some(to) {
+            continue;
+        }
+        log::info!(binary = config.name(), from:? = from, to = to; "Reinstalling binary");
+        config.download(prefix, Some(to))?;
+        report.binaries.push(UpdatedBinary {
+            name: config.name(),
+            from: from.cloned(),
+            to: to.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Regenerate the rc file still holding the managed block when it is outdated.
+fn update_rc(prefix: &Prefix, args: &UpdateArgs, report: &mut UpdateReport) -> Result<()> {
+    if let Some(path) = outdated_rc(prefix, args.shell, args.rc_file.as_deref())? {
+        args.shell.generate_rc(prefix, args.rc_file.as_deref())?;
+        report.rc_files.push(path);
+    }
+    Ok(())
+}
+
+/// Regenerate the ssh configs of `config.d` whose predefined key changed.
+fn update_ssh_configs(prefix: &Prefix, report: &mut UpdateReport) -> Result<()> {
+    for key in outdated_ssh_configs(prefix)? {
+        key.generate_ssh_config(prefix)?;
+        report.ssh_configs.push(key.key().to_owned());
+    }
+    Ok(())
+}
+
+fn update(prefix: &Prefix, args: &UpdateArgs) -> Result<UpdateReport> {
+    let mut report = UpdateReport::default();
+    let versions = load_version(prefix)?;
+    update_repo(prefix, &mut report)?;
+    update_binaries(prefix, &versions, &mut report)?;
+    update_rc(prefix, args, &mut report)?;
+    update_ssh_configs(prefix, &mut report)?;
+    if prefix.config_plugin().join("plugins.toml").exists() {
+        report.new_plugins = plugin::install(prefix, &[])?.installed().to_vec();
+        report.plugins = plugin::update(prefix, &[])?.updated().to_vec();
+    }
+
+    if !args.no_self_update {
+        report.dot = Some(self_update(prefix, &SelfUpdateArgs { version: None, force: false })?);
+    }
+    Ok(report)
+}
+
+pub fn entry_update(prefix: &Prefix, args: UpdateArgs) -> Result<()> {
+    prefix.emit(&update(prefix, &args)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::git::clone;
+    use crate::init::block;
+    use crate::utils::commit;
+
+    #[test]
+    fn test_update() {
+        let temp_dir = TempDir::new().unwrap();
+        let prefix: Prefix = (&temp_dir).into();
+        let upstream = Repository::init(temp_dir.path().join("upstream")).unwrap();
+        commit(&upstream, "README.md", "dot");
+        let branch = upstream.head().unwrap().shorthand().unwrap().to_owned();
+        let first = commit(&upstream, "config/dot.toml", &format!("branch = {:?}\n", branch));
+        clone(&prefix, upstream.path().to_str().unwrap(), prefix.dot(), false).unwrap();
+        prefix.create_dir_all().unwrap();
+
+        let args = UpdateArgs { shell: Shell::Zsh, rc_file: None, no_self_update: true };
+        let report = update(&prefix, &args).unwrap();
+        assert!(report.repo.is_none());
+        assert!(report.rc_files.is_empty());
+
+        let second = commit(&upstream, "README.md", "dot update");
+        let zshenv = prefix.prefix().join(".zshenv");
+        block::write(&prefix, &zshenv, "export DOTDIR=/old\n").unwrap();
+        let report = update(&prefix, &args).unwrap();
+        assert_eq!(report.repo, Some((first.to_string(), second.to_string())));
+        assert_eq!(report.rc_files, [zshenv.clone()]);
+        assert!(Shell::Zsh.is_rc_up_to_date(&prefix, &std::fs::read_to_string(&zshenv).unwrap()));
+        assert_eq!(std::fs::read_to_string(prefix.dot().join("README.md")).unwrap(), "dot update");
+    }
+}
@@ -1,10 +1,11 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use clap::{CommandFactory, Error};
+use clap::CommandFactory;
 use serde::de::DeserializeOwned;
 
 use crate::Cli;
+use crate::error::{Result, WithPath};
 
 pub fn parse_addition(s: &str) -> Result<(String, String), &'static str> {
     s.split_once('=')
@@ -16,7 +17,7 @@ pub fn unwrap_or_missing_argument<T>(
     option: Option<T>,
     key: &str,
     cause: Option<&str>,
-) -> Result<T, Error> {
+) -> Result<T, clap::Error> {
     option.ok_or_else(|| {
         Cli::command().error(
             clap::error::ErrorKind::MissingRequiredArgument,
@@ -25,25 +26,77 @@ pub fn unwrap_or_missing_argument<T>(
     })
 }
 
+pub fn load_toml<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T> {
+    let path = path.as_ref();
+    toml::from_str(&std::fs::read_to_string(path).with_path(path)?).with_path(path)
+}
+
 pub fn load_predefined_and_local<T: DeserializeOwned>(
     path: impl AsRef<Path>,
-) -> HashMap<String, T> {
-    let config = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+) -> Result<HashMap<String, T>> {
+    let config: HashMap<String, T> = load_toml(&path)?;
     let local_path = path.as_ref().with_file_name(".local.toml");
     if !local_path.exists() {
-        config
+        Ok(config)
     } else {
-        config
-            .into_iter()
-            .chain(
-                toml::from_str::<HashMap<_, _>>(&std::fs::read_to_string(local_path).unwrap())
-                    .unwrap(),
-            )
-            .collect()
+        Ok(config.into_iter().chain(load_toml::<HashMap<_, _>>(local_path)?).collect())
     }
 }
 
+/// Directories of the dot repository never searched by `find_files`.
+const SKIPPED_DIRS: [&str; 3] = [".git", ".local", "target"];
+
+/// Every file with `extension` under `dir`, sorted.
+pub fn find_files(dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(dir).with_path(dir)? {
+            let path = entry.with_path(dir)?.path();
+            if path.is_dir() {
+                if !path.file_name().is_some_and(|name| SKIPPED_DIRS.iter().any(|dir| name == *dir))
+                {
+                    walk(&path, extension, files)?;
+                }
+            } else if path.extension().is_some_and(|ext| ext == extension) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = vec![];
+    walk(dir, extension, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// `path` after the moves of `moves` from their first path to their second one,
+/// `None` if none of them contains it.
+pub fn rebase(path: &Path, moves: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    moves.iter().find_map(|(from, to)| {
+        path.strip_prefix(from)
+            .ok()
+            .map(|rest| if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) })
+    })
+}
+
 #[cfg(test)]
 pub fn get_dot_dir() -> std::path::PathBuf {
     std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
 }
+
+/// Write `content` to `file` of `repo` and commit it on top of `HEAD`.
+#[cfg(test)]
+pub fn commit(repo: &git2::Repository, file: &str, content: &str) -> git2::Oid {
+    let path = repo.workdir().unwrap().join(file);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new(file)).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("dot", "dot@test").unwrap();
+    let parents: Vec<_> =
+        repo.head().ok().map(|head| head.peel_to_commit().unwrap()).into_iter().collect();
+    let parents: Vec<_> = parents.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, file, &tree, &parents).unwrap()
+}